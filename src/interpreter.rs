@@ -0,0 +1,170 @@
+// An embeddable felisp interpreter, wrapping a global env and the
+// tokenize, parse, eval pipeline
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::lib::convert::{FromFelisp, IntoFelisp};
+use crate::lib::data::*;
+use crate::lisp_core::env::default_env;
+use crate::lisp_core::eval::{clear_interrupt, env_get, eval};
+use crate::lisp_core::parser::{parse, skip_datum_comments};
+use crate::lisp_core::tokenizer::tokenize;
+
+pub struct Interpreter {
+    env: FelispEnvRef,
+}
+
+impl Interpreter {
+    // An interpreter with the default builtins and tables
+    pub fn new() -> Interpreter {
+        Interpreter {
+            env: Rc::new(RefCell::new(default_env())),
+        }
+    }
+
+    pub fn env(&self) -> &FelispEnvRef {
+        &self.env
+    }
+
+    // Read every top-level form in `src`, without evaluating them
+    pub fn read(&self, src: &str) -> Result<Vec<FelispExp>, FelispErr> {
+        let tokens = tokenize(src.to_string())?;
+        let mut rest = skip_datum_comments(&tokens)?;
+        let mut forms = vec![];
+        while !rest.is_empty() {
+            let (exp, new_rest) = parse(rest)?;
+            forms.push(exp);
+            rest = skip_datum_comments(new_rest)?;
+        }
+        Ok(forms)
+    }
+
+    pub fn eval(&mut self, exp: &FelispExp) -> Result<FelispExp, FelispErr> {
+        clear_interrupt();
+        eval(exp, &self.env)
+    }
+
+    // Evaluate every form in `src` in order, returning the value of the
+    // last one, nil if there are none
+    pub fn eval_str(&mut self, src: &str) -> Result<FelispExp, FelispErr> {
+        let forms = self.read(src)?;
+        clear_interrupt();
+        let mut res = FelispExp::Nil;
+        for exp in forms {
+            res = eval(&exp, &self.env)?;
+        }
+        Ok(res)
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<FelispExp, FelispErr> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path).map_err(|e| {
+            FelispErr::io(format!("could not read {}: {}", path.display(), e))
+        })?;
+        self.eval_str(&src)
+    }
+
+    // Register a Rust closure as a global function called `name`
+    pub fn define_builtin<F>(&mut self, name: &str, arity: Arity, func: F)
+    where
+        F: Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr> + 'static,
+    {
+        let func = FelispExp::Func(FelispFunc::new(name, arity, func));
+        self.env.borrow_mut().data.insert(name.to_string(), func);
+    }
+
+    pub fn get_global<T: FromFelisp>(&self, name: &str) -> Result<T, FelispErr> {
+        let exp = env_get(name, &self.env)
+            .ok_or_else(|| FelispErr::unbound(name))?;
+        T::from_felisp(&exp)
+    }
+
+    pub fn set_global<T: IntoFelisp>(&mut self, name: &str, value: T) {
+        self.env
+            .borrow_mut()
+            .data
+            .insert(name.to_string(), value.into_felisp());
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(err: FelispErr) -> String {
+        err.to_string()
+    }
+
+    #[test]
+    fn test_eval_str_runs_every_form() {
+        let mut interp = Interpreter::new();
+        let res = interp.eval_str("(defn x 2) #_ignored\n(defn y (* x 3)) (+ x y) #_(z)");
+        assert_eq!(res.ok().map(|exp| exp.to_string()).unwrap(), "8");
+        assert_eq!(interp.eval_str("  ; nothing\n").ok().unwrap().to_string(), "nil");
+        assert_eq!(interp.get_global::<i64>("y").unwrap(), 6);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut interp = Interpreter::new();
+        let forms = interp.read("(a 1)\n#_(skipped) 'b ; done").unwrap();
+        let forms: Vec<String> = forms.iter().map(|f| f.to_string()).collect();
+        assert_eq!(forms, vec!["(a 1)", "(quote b)"]);
+        // Printed data reads back as the same data
+        let printed = interp.eval_str("'(a (b \"c,d\") 1.5 ())").unwrap().to_string();
+        assert_eq!(printed, "(a (b \"c,d\") 1.5 ())");
+        assert_eq!(interp.read(&printed).unwrap()[0].to_string(), printed);
+        assert!(interp.read("(a))").is_err());
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut interp = Interpreter::new();
+        interp.set_global("greeting", "hello");
+        interp.set_global("limit", 10i64);
+        let res = interp.eval_str("(if (> limit 5) greeting \"small\")").ok().unwrap();
+        assert_eq!(String::from_felisp(&res).unwrap(), "hello");
+        assert_eq!(
+            interp.get_global::<i64>("nope").err().map(message).unwrap(),
+            "unbound symbol k='nope'"
+        );
+        assert_eq!(
+            interp.get_global::<i64>("greeting").err().map(message).unwrap(),
+            "expected an integer, got \"hello\""
+        );
+    }
+
+    #[test]
+    fn test_define_builtin() {
+        let mut interp = Interpreter::new();
+        let log = Rc::new(RefCell::new(vec![]));
+        let sink = log.clone();
+        interp.define_builtin("log!", Arity::Exactly(1), move |args, _| {
+            sink.borrow_mut().push(args[0].to_string());
+            Ok(FelispExp::Nil)
+        });
+        interp.eval_str("(log! 1) (map log! '(\"a\" b))").ok().unwrap();
+        assert_eq!(*log.borrow(), vec!["1", "\"a\"", "b"]);
+    }
+
+    #[test]
+    fn test_eval_file() {
+        let path = std::env::temp_dir().join("felisp_test_eval_file.flp");
+        fs::write(&path, "(defn sq (fn (x) (* x x)))\n(sq 7)\n").unwrap();
+        let mut interp = Interpreter::new();
+        let res = interp.eval_file(&path).ok().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(i64::from_felisp(&res).unwrap(), 49);
+        let missing = interp.eval_file("/no/such/file.flp").err().map(message).unwrap();
+        assert!(missing.starts_with("could not read /no/such/file.flp: "));
+    }
+}
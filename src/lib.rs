@@ -0,0 +1,16 @@
+//! Felisp
+//! A Simple lisp inspired by Peter Norvig's lispy and risp by @stopachka
+//!
+//! The interpreter as a library: `Interpreter` holds a global env, reads and
+//! evaluates source in it, and lets a host register builtins and exchange
+//! values with felisp through `IntoFelisp` and `FromFelisp`.
+
+// `src/lib/` predates this crate root, so it needs its path spelled out
+#[path = "lib/mod.rs"]
+pub mod lib;
+pub mod lisp_core;
+pub mod interpreter;
+
+pub use interpreter::Interpreter;
+pub use lib::convert::{from_felisp, to_felisp, FromFelisp, IntoFelisp};
+pub use lib::data::{Arity, Context, FelispErr, FelispExp, FelispFunc};
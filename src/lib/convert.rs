@@ -0,0 +1,390 @@
+// Conversions between Rust values and felisp expressions, for hosts that
+// pass values in and out of an `Interpreter`. Structs go through serde, see
+// `to_felisp` and `from_felisp`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+
+use num_bigint::BigInt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+use crate::lib::data::*;
+use crate::lisp_core::numeric::Num;
+
+pub trait IntoFelisp {
+    fn into_felisp(self) -> FelispExp;
+}
+
+pub trait FromFelisp: Sized {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr>;
+}
+
+impl IntoFelisp for FelispExp {
+    fn into_felisp(self) -> FelispExp {
+        self
+    }
+}
+
+impl FromFelisp for FelispExp {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        Ok(exp.clone())
+    }
+}
+
+impl IntoFelisp for () {
+    fn into_felisp(self) -> FelispExp {
+        FelispExp::Nil
+    }
+}
+
+impl IntoFelisp for bool {
+    fn into_felisp(self) -> FelispExp {
+        FelispExp::Bool(self)
+    }
+}
+
+impl FromFelisp for bool {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        match exp {
+            FelispExp::Bool(b) => Ok(*b),
+            _ => Err(FelispErr::type_error("a bool", exp)),
+        }
+    }
+}
+
+// Integers become bignums when they do not fit an i64, and converting back
+// fails when the value does not fit the Rust type
+macro_rules! int_conversions {
+    ($($t:ty),*) => {$(
+        impl IntoFelisp for $t {
+            fn into_felisp(self) -> FelispExp {
+                match i64::try_from(self) {
+                    Ok(n) => FelispExp::Int(n),
+                    Err(_) => FelispExp::BigInt(BigInt::from(self)),
+                }
+            }
+        }
+
+        impl FromFelisp for $t {
+            fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+                let n = match exp {
+                    FelispExp::Int(n) => <$t>::try_from(*n).ok(),
+                    FelispExp::BigInt(n) => <$t>::try_from(n).ok(),
+                    _ => return Err(FelispErr::type_error("an integer", exp)),
+                };
+                n.ok_or_else(|| {
+                    FelispErr::type_error(concat!("an integer in the range of ", stringify!($t)), exp)
+                })
+            }
+        }
+    )*};
+}
+
+int_conversions!(i32, i64, u32, u64, usize);
+
+impl IntoFelisp for f64 {
+    fn into_felisp(self) -> FelispExp {
+        FelispExp::Float(self)
+    }
+}
+
+// Any number converts to a float, exact ones may lose precision
+impl FromFelisp for f64 {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        Ok(Num::from_exp(exp)?.to_f64())
+    }
+}
+
+impl IntoFelisp for String {
+    fn into_felisp(self) -> FelispExp {
+        FelispExp::Str(self)
+    }
+}
+
+impl IntoFelisp for &str {
+    fn into_felisp(self) -> FelispExp {
+        FelispExp::Str(self.to_string())
+    }
+}
+
+impl FromFelisp for String {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        match exp {
+            FelispExp::Str(s) => Ok(s.clone()),
+            _ => Err(FelispErr::type_error("a string", exp)),
+        }
+    }
+}
+
+impl<T: IntoFelisp> IntoFelisp for Option<T> {
+    fn into_felisp(self) -> FelispExp {
+        match self {
+            Some(x) => x.into_felisp(),
+            None => FelispExp::Nil,
+        }
+    }
+}
+
+impl<T: FromFelisp> FromFelisp for Option<T> {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        match exp {
+            FelispExp::Nil => Ok(None),
+            _ => T::from_felisp(exp).map(Some),
+        }
+    }
+}
+
+impl<T: IntoFelisp> IntoFelisp for Vec<T> {
+    fn into_felisp(self) -> FelispExp {
+        FelispExp::List(self.into_iter().map(IntoFelisp::into_felisp).collect())
+    }
+}
+
+impl<T: FromFelisp> FromFelisp for Vec<T> {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        match exp {
+            FelispExp::List(list) => list.iter().map(T::from_felisp).collect(),
+            _ => Err(FelispErr::type_error("a list", exp)),
+        }
+    }
+}
+
+impl<T: IntoFelisp, S: BuildHasher> IntoFelisp for HashMap<String, T, S> {
+    fn into_felisp(self) -> FelispExp {
+        FelispExp::Map(self.into_iter().map(|(k, v)| (k, v.into_felisp())).collect())
+    }
+}
+
+impl<T: FromFelisp, S: BuildHasher + Default> FromFelisp for HashMap<String, T, S> {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        match exp {
+            FelispExp::Map(map) => map
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), T::from_felisp(v)?)))
+                .collect(),
+            _ => Err(FelispErr::type_error("a map", exp)),
+        }
+    }
+}
+
+// Tuples are lists of a fixed length
+macro_rules! tuple_conversions {
+    ($len:expr => $($t:ident $i:tt),*) => {
+        impl<$($t: IntoFelisp),*> IntoFelisp for ($($t,)*) {
+            fn into_felisp(self) -> FelispExp {
+                FelispExp::List(vec![$(self.$i.into_felisp()),*])
+            }
+        }
+
+        impl<$($t: FromFelisp),*> FromFelisp for ($($t,)*) {
+            fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+                match exp {
+                    FelispExp::List(list) if list.len() == $len => {
+                        Ok(($($t::from_felisp(&list[$i])?,)*))
+                    }
+                    _ => Err(FelispErr::type_error(&format!("a list of {} elements", $len), exp)),
+                }
+            }
+        }
+    };
+}
+
+tuple_conversions!(2 => A 0, B 1);
+tuple_conversions!(3 => A 0, B 1, C 2);
+tuple_conversions!(4 => A 0, B 1, C 2, D 3);
+
+// Convert anything serde can serialize, structs become maps keyed by their
+// field names
+pub fn to_felisp<T: Serialize>(value: &T) -> Result<FelispExp, FelispErr> {
+    let value = serde_json::to_value(value).map_err(|e| {
+        FelispErr::new(FelispErrKind::Type {
+            expected: "serializable data".to_string(),
+            got: e.to_string(),
+        })
+    })?;
+    value_to_exp(value)
+}
+
+// The inverse of `to_felisp`, maps fill in struct fields by name
+pub fn from_felisp<T: DeserializeOwned>(exp: &FelispExp) -> Result<T, FelispErr> {
+    serde_json::from_value(exp_to_value(exp)?).map_err(|e| {
+        let name = std::any::type_name::<T>();
+        FelispErr::new(FelispErrKind::Type {
+            expected: format!("a {}", name.rsplit("::").next().unwrap_or(name)),
+            got: format!("{} ({})", exp, e),
+        })
+    })
+}
+
+fn value_to_exp(value: Value) -> Result<FelispExp, FelispErr> {
+    Ok(match value {
+        Value::Null => FelispExp::Nil,
+        Value::Bool(b) => FelispExp::Bool(b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(i), _, _) => FelispExp::Int(i),
+            (None, Some(u), _) => FelispExp::BigInt(BigInt::from(u)),
+            (None, None, Some(f)) => FelispExp::Float(f),
+            // Only possible with serde_json's arbitrary precision numbers
+            (None, None, None) => {
+                return Err(FelispErr::new(FelispErrKind::Type {
+                    expected: "a number that fits in a float".to_string(),
+                    got: n.to_string(),
+                }))
+            }
+        },
+        Value::String(s) => FelispExp::Str(s),
+        Value::Array(xs) => {
+            FelispExp::List(xs.into_iter().map(value_to_exp).collect::<Result<_, _>>()?)
+        }
+        Value::Object(map) => FelispExp::Map(
+            map.into_iter()
+                .map(|(k, v)| Ok((k, value_to_exp(v)?)))
+                .collect::<Result<_, FelispErr>>()?,
+        ),
+    })
+}
+
+fn exp_to_value(exp: &FelispExp) -> Result<Value, FelispErr> {
+    let unsupported = || FelispErr::type_error("plain data", exp);
+    match exp {
+        FelispExp::Nil => Ok(Value::Null),
+        FelispExp::Bool(b) => Ok(Value::Bool(*b)),
+        FelispExp::Int(n) => Ok(Value::from(*n)),
+        FelispExp::BigInt(n) => u64::try_from(n).map(Value::from).map_err(|_| unsupported()),
+        FelispExp::Rational(_) | FelispExp::Float(_) => {
+            let f = Num::from_exp(exp)?.to_f64();
+            Number::from_f64(f).map(Value::Number).ok_or_else(unsupported)
+        }
+        FelispExp::Str(s) => Ok(Value::String(s.clone())),
+        // `:id` names the field "id", like a map key
+        FelispExp::Symbol(s) => Ok(Value::String(s.trim_start_matches(':').to_string())),
+        FelispExp::List(list) => list.iter().map(exp_to_value).collect(),
+        FelispExp::Map(map) => map
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), exp_to_value(v)?)))
+            .collect(),
+        FelispExp::Table(t) => serde_json::to_value(&*t.borrow()).map_err(|_| unsupported()),
+        FelispExp::Func(_) | FelispExp::Lambda(_) | FelispExp::Macro(_) => Err(unsupported()),
+    }
+}
+
+impl IntoFelisp for Row {
+    fn into_felisp(self) -> FelispExp {
+        let mut map = BTreeMap::new();
+        map.insert("id".to_string(), self.id.into_felisp());
+        map.insert("username".to_string(), self.username.into_felisp());
+        map.insert("email".to_string(), self.email.into_felisp());
+        FelispExp::Map(map)
+    }
+}
+
+impl FromFelisp for Row {
+    fn from_felisp(exp: &FelispExp) -> Result<Self, FelispErr> {
+        from_felisp(exp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(err: FelispErr) -> String {
+        err.to_string()
+    }
+
+    #[test]
+    fn test_scalar_round_trips() {
+        assert!(bool::from_felisp(&true.into_felisp()).unwrap());
+        assert_eq!(i64::from_felisp(&42i64.into_felisp()).unwrap(), 42);
+        assert_eq!(i32::from_felisp(&(-7i32).into_felisp()).unwrap(), -7);
+        assert_eq!(f64::from_felisp(&1.5.into_felisp()).unwrap(), 1.5);
+        assert_eq!(String::from_felisp(&"hi".into_felisp()).unwrap(), "hi");
+        assert_eq!(().into_felisp().to_string(), "nil");
+    }
+
+    #[test]
+    fn test_conversion_errors() {
+        assert_eq!(
+            i32::from_felisp(&FelispExp::Int(1 << 40)).err().map(message).unwrap(),
+            "expected an integer in the range of i32, got 1099511627776"
+        );
+        assert_eq!(
+            String::from_felisp(&FelispExp::Int(1)).err().map(message).unwrap(),
+            "expected a string, got 1"
+        );
+        assert_eq!(f64::from_felisp(&FelispExp::Int(2)).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_collections() {
+        let xs = vec![Some(1i64), None];
+        assert_eq!(xs.clone().into_felisp().to_string(), "(1 nil)");
+        assert_eq!(Vec::<Option<i64>>::from_felisp(&xs.into_felisp()).unwrap(), vec![Some(1), None]);
+        let mut scores = HashMap::new();
+        scores.insert("amy".to_string(), 3u32);
+        scores.insert("bob".to_string(), 5u32);
+        let exp = scores.clone().into_felisp();
+        assert_eq!(exp.to_string(), "{:amy 3, :bob 5}");
+        assert_eq!(HashMap::<String, u32>::from_felisp(&exp).unwrap(), scores);
+        let pair = (1i32, "x".to_string());
+        assert_eq!(<(i32, String)>::from_felisp(&pair.clone().into_felisp()).unwrap(), pair);
+        assert_eq!(
+            <(i32, i32)>::from_felisp(&vec![1i32].into_felisp()).err().map(message).unwrap(),
+            "expected a list of 2 elements, got (1)"
+        );
+        assert_eq!(u64::MAX.into_felisp().to_string(), "18446744073709551615");
+        assert_eq!(u64::from_felisp(&u64::MAX.into_felisp()).unwrap(), u64::MAX);
+        assert_eq!(
+            u32::from_felisp(&FelispExp::Int(-1)).err().map(message).unwrap(),
+            "expected an integer in the range of u32, got -1"
+        );
+    }
+
+    #[test]
+    fn test_row_round_trip() {
+        let row = Row {
+            id: 5,
+            username: "bob".to_string(),
+            email: "bob@x.com".to_string(),
+        };
+        let exp = row.clone().into_felisp();
+        assert_eq!(exp.to_string(), r#"{:email "bob@x.com", :id 5, :username "bob"}"#);
+        assert_eq!(Row::from_felisp(&exp).unwrap(), row);
+        assert_eq!(to_felisp(&row).unwrap().to_string(), exp.to_string());
+        assert_eq!(
+            Row::from_felisp(&FelispExp::Int(1)).err().map(message).unwrap(),
+            "expected a Row, got 1 (invalid type: integer `1`, expected struct Row)"
+        );
+    }
+
+    #[test]
+    fn test_serde_bridge() {
+        #[derive(Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Query {
+            table: String,
+            limit: Option<u32>,
+            ratio: f64,
+            tags: Vec<String>,
+        }
+        let query = Query {
+            table: "mytable1".to_string(),
+            limit: None,
+            ratio: 0.5,
+            tags: vec!["a".to_string()],
+        };
+        let exp = to_felisp(&query).unwrap();
+        assert_eq!(exp.to_string(), r#"{:limit nil, :ratio 0.5, :table "mytable1", :tags ("a")}"#);
+        assert_eq!(from_felisp::<Query>(&exp).unwrap(), query);
+        let mut map = match exp {
+            FelispExp::Map(map) => map,
+            _ => panic!("expected a map"),
+        };
+        map.insert("ratio".to_string(), FelispExp::Int(1));
+        map.insert("table".to_string(), FelispExp::Symbol(":other".to_string()));
+        let query = from_felisp::<Query>(&FelispExp::Map(map)).unwrap();
+        assert_eq!((query.ratio, &query.table[..]), (1.0, "other"));
+    }
+}
@@ -1,69 +1,342 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
-use std::io;
-use std::io::{stdin, stdout, Write};
-use std::num::ParseFloatError;
-use std::process;
 use std::rc::Rc;
 
-use std::error::Error;
-use std::path::Path;
-use std::fs::File;
-
-use std::io::BufWriter;
-use std::io::BufReader;
-use std::io::Cursor;
-use std::io::Read;
-use std::io::SeekFrom;
-use std::io::Seek;
-use std::io::BufRead;
-
+use num_bigint::BigInt;
+use num_rational::BigRational;
 use serde::{Serialize, Deserialize};
-use bincode; // serialize_into will be useful
 
 
 #[derive(Clone)]
 pub enum FelispExp {
+    Nil,
     Bool(bool),
     Symbol(String),
-    Number(f64),
+    Str(String),
+    Int(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    Float(f64),
     List(Vec<FelispExp>),
-    Func(fn(&[FelispExp]) -> Result<FelispExp, FelispErr>), // function evaluations
+    Map(BTreeMap<String, FelispExp>), // keyed by keyword names, `:id` is stored as "id"
+    Func(FelispFunc), // function evaluations
     Lambda(FelispLambda),
-    Table(Table),
+    Macro(FelispLambda), // called on its unevaluated forms, its value is evaluated in their place
+    Table(TableRef), // shared, so naming a table neither copies its rows nor its changes
+}
+
+#[derive(Debug)]
+pub struct FelispErr {
+    pub kind: FelispErrKind,
+    // The functions the error unwound through, innermost first. A tail
+    // call replaces its caller's frame, so it is not listed.
+    pub stack: Vec<String>,
 }
 
 #[derive(Debug)]
-pub enum FelispErr {
-    Reason(String),
+pub enum FelispErrKind {
+    Read(String),   // from the tokenizer and the parser
+    Incomplete(String), // a read that ran out inside a string or block comment
+    Syntax(String), // a malformed special form
+    UnboundSymbol(String),
+    Type { expected: String, got: String },
+    Arity { name: String, expected: String, got: usize },
+    Arithmetic(String),
+    Db(String),
+    Io(String),
+    User(FelispExp), // thrown from felisp code
+    Exit(i32),       // (exit code), unwinding to the host to exit with it
+    Interrupted,     // stopped by `interrupt`, from Ctrl-C say
+}
+
+impl FelispErr {
+    pub fn new(kind: FelispErrKind) -> FelispErr {
+        FelispErr { kind, stack: vec![] }
+    }
+
+    pub fn read(msg: impl Into<String>) -> FelispErr {
+        FelispErr::new(FelispErrKind::Read(msg.into()))
+    }
+
+    pub fn syntax(msg: impl Into<String>) -> FelispErr {
+        FelispErr::new(FelispErrKind::Syntax(msg.into()))
+    }
+
+    pub fn unbound(k: &str) -> FelispErr {
+        FelispErr::new(FelispErrKind::UnboundSymbol(k.to_string()))
+    }
+
+    pub fn type_error(expected: &str, got: &FelispExp) -> FelispErr {
+        FelispErr::new(FelispErrKind::Type {
+            expected: expected.to_string(),
+            got: got.to_string(),
+        })
+    }
+
+    pub fn arity(name: &str, expected: impl Into<String>, got: usize) -> FelispErr {
+        FelispErr::new(FelispErrKind::Arity {
+            name: name.to_string(),
+            expected: expected.into(),
+            got,
+        })
+    }
+
+    pub fn arithmetic(msg: impl Into<String>) -> FelispErr {
+        FelispErr::new(FelispErrKind::Arithmetic(msg.into()))
+    }
 
+    pub fn db(msg: impl Into<String>) -> FelispErr {
+        FelispErr::new(FelispErrKind::Db(msg.into()))
+    }
+
+    pub fn io(msg: impl Into<String>) -> FelispErr {
+        FelispErr::new(FelispErrKind::Io(msg.into()))
+    }
+
+    // The error without its stack
+    pub fn message(&self) -> String {
+        self.kind.to_string()
+    }
+
+    pub fn in_frame(mut self, name: &str) -> FelispErr {
+        self.stack.push(name.to_string());
+        self
+    }
+}
+
+impl FelispErrKind {
+    // What `catch` calls this kind, as in (catch :type-error e ...)
+    pub fn name(&self) -> &'static str {
+        match self {
+            FelispErrKind::Read(_) | FelispErrKind::Incomplete(_) => "read-error",
+            FelispErrKind::Syntax(_) => "syntax-error",
+            FelispErrKind::UnboundSymbol(_) => "unbound-symbol",
+            FelispErrKind::Type { .. } => "type-error",
+            FelispErrKind::Arity { .. } => "arity-error",
+            FelispErrKind::Arithmetic(_) => "arithmetic-error",
+            FelispErrKind::Db(_) => "db-error",
+            FelispErrKind::Io(_) => "io-error",
+            FelispErrKind::User(_) => "user-error",
+            FelispErrKind::Exit(_) => "exit",
+            FelispErrKind::Interrupted => "interrupted",
+        }
+    }
 }
+
+impl fmt::Display for FelispErrKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FelispErrKind::Read(msg)
+            | FelispErrKind::Incomplete(msg)
+            | FelispErrKind::Syntax(msg)
+            | FelispErrKind::Arithmetic(msg)
+            | FelispErrKind::Db(msg)
+            | FelispErrKind::Io(msg) => write!(f, "{}", msg),
+            FelispErrKind::UnboundSymbol(k) => write!(f, "unbound symbol k='{}'", k),
+            FelispErrKind::Type { expected, got } => write!(f, "expected {}, got {}", expected, got),
+            FelispErrKind::Arity { name, expected, got } => {
+                write!(f, "{} expected {}, got {}", name, expected, got)
+            }
+            FelispErrKind::User(FelispExp::Str(msg)) => write!(f, "{}", msg),
+            FelispErrKind::User(exp) => write!(f, "{}", exp),
+            FelispErrKind::Exit(code) => write!(f, "exit with status {}", code),
+            FelispErrKind::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl fmt::Display for FelispErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        for frame in &self.stack {
+            write!(f, "\n  in {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FelispErr {}
+
+// Environment frames are shared: a lambda keeps the frame it was created
+// in alive for as long as the lambda itself is reachable.
 #[derive(Clone)]
-pub struct FelispEnv<'a> {
+pub struct FelispEnv {
     pub data: HashMap<String, FelispExp>,
-    pub outer: Option<&'a FelispEnv<'a>>,
+    pub outer: Option<FelispEnvRef>,
 }
 
+pub type FelispEnvRef = Rc<RefCell<FelispEnv>>;
+
+pub type TableRef = Rc<RefCell<Table>>;
+
 #[derive(Clone)]
 pub struct FelispLambda {
+    pub name: Option<String>, // set by `defn`, for call stacks
     pub params_exp: Rc<FelispExp>,
     pub body_exp: Rc<FelispExp>,
+    pub env: FelispEnvRef,
+}
+
+pub type FelispFn = dyn Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr>;
+
+// A builtin implemented in Rust. Being a closure it can keep state of its
+// own, such as a DB handle or a counter, and through the `Context` it can
+// reach the env it is called from.
+#[derive(Clone)]
+pub struct FelispFunc {
+    pub name: String,
+    pub arity: Arity,
+    pub func: Rc<FelispFn>,
+}
+
+impl FelispFunc {
+    pub fn new<F>(name: &str, arity: Arity, func: F) -> FelispFunc
+    where
+        F: Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr> + 'static,
+    {
+        FelispFunc {
+            name: name.to_string(),
+            arity,
+            func: Rc::new(func),
+        }
+    }
+
+    // The arity is checked here, so `func` can rely on getting as many
+    // arguments as it asked for
+    pub fn call(&self, args: &[FelispExp], ctx: &mut Context) -> Result<FelispExp, FelispErr> {
+        self.arity.check(&self.name, args.len())?;
+        (self.func)(args, ctx).map_err(|e| e.in_frame(&self.name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn check(&self, name: &str, n: usize) -> Result<(), FelispErr> {
+        let ok = match *self {
+            Arity::Exactly(k) => n == k,
+            Arity::AtLeast(k) => n >= k,
+            Arity::Between(lo, hi) => lo <= n && n <= hi,
+        };
+        if ok {
+            return Ok(());
+        }
+        Err(FelispErr::arity(name, self.describe(), n))
+    }
+
+    // How many arguments this is, as in "at least 1 argument"
+    pub fn describe(&self) -> String {
+        let (expected, count) = match *self {
+            Arity::Exactly(k) => (k.to_string(), k),
+            Arity::AtLeast(k) => (format!("at least {}", k), k),
+            Arity::Between(0, hi) => (format!("at most {}", hi), hi),
+            Arity::Between(lo, hi) => (format!("{} to {}", lo, hi), hi),
+        };
+        let noun = if count == 1 { "argument" } else { "arguments" };
+        format!("{} {}", expected, noun)
+    }
+}
+
+// What a builtin gets to see of the evaluation it is called from
+pub struct Context {
+    pub env: FelispEnvRef,
+}
+
+// Shows the variant, so that a failed assert tells `Int(1)` from
+// `Str("1")`. Functions show only their name, as their envs can be cyclic.
+impl fmt::Debug for FelispExp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FelispExp::Nil => write!(f, "Nil"),
+            FelispExp::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            FelispExp::Symbol(s) => f.debug_tuple("Symbol").field(s).finish(),
+            FelispExp::Str(s) => f.debug_tuple("Str").field(s).finish(),
+            FelispExp::Int(n) => f.debug_tuple("Int").field(n).finish(),
+            FelispExp::BigInt(n) => f.debug_tuple("BigInt").field(n).finish(),
+            FelispExp::Rational(n) => f.debug_tuple("Rational").field(n).finish(),
+            FelispExp::Float(n) => f.debug_tuple("Float").field(n).finish(),
+            FelispExp::List(xs) => f.debug_tuple("List").field(xs).finish(),
+            FelispExp::Map(map) => f.debug_tuple("Map").field(map).finish(),
+            FelispExp::Func(func) => write!(f, "Func({})", func.name),
+            FelispExp::Lambda(lambda) => write!(f, "Lambda({})", lambda.frame_name()),
+            FelispExp::Macro(lambda) => write!(f, "Macro({})", lambda.frame_name()),
+            FelispExp::Table(t) => write!(f, "Table({})", t.borrow().name),
+        }
+    }
+}
+
+impl FelispLambda {
+    pub fn frame_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("fn")
+    }
+
+    // A string leading a body of several forms, as in (fn (x) "Doc." x)
+    pub fn doc(&self) -> Option<&str> {
+        match &*self.body_exp {
+            FelispExp::List(forms) => match &forms[..] {
+                [FelispExp::Symbol(head), FelispExp::Str(doc), _, ..] if head == "do" => Some(doc),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl FelispExp {
+    // What kind of value this is, for the REPL to show
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            FelispExp::Nil => "nil",
+            FelispExp::Bool(_) => "bool",
+            FelispExp::Symbol(s) if s.len() > 1 && s.starts_with(':') => "keyword",
+            FelispExp::Symbol(_) => "symbol",
+            FelispExp::Str(_) => "string",
+            FelispExp::Int(_) | FelispExp::BigInt(_) => "int",
+            FelispExp::Rational(_) => "rational",
+            FelispExp::Float(_) => "float",
+            FelispExp::List(_) => "list",
+            FelispExp::Map(_) => "map",
+            FelispExp::Func(_) => "builtin",
+            FelispExp::Lambda(_) => "fn",
+            FelispExp::Macro(_) => "macro",
+            FelispExp::Table(_) => "table",
+        }
+    }
 }
 
 impl fmt::Display for FelispExp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let str = match self {
+            FelispExp::Nil => "nil".to_string(),
             FelispExp::Symbol(s) => s.clone(),
-            FelispExp::Number(n) => n.to_string(),
+            FelispExp::Str(s) => escape_string(s),
+            FelispExp::Int(n) => n.to_string(),
+            FelispExp::BigInt(n) => n.to_string(),
+            FelispExp::Rational(n) => n.to_string(),
+            // Debug keeps the `.0` on whole floats, so they never print
+            // like integers
+            FelispExp::Float(n) => format!("{:?}", n),
             FelispExp::List(list) => {
                 let xs: Vec<String> = list.iter().map(|x| x.to_string()).collect();
-                format!("({})", xs.join(","))
+                format!("({})", xs.join(" "))
+            }
+            FelispExp::Map(map) => {
+                let xs: Vec<String> = map.iter().map(|(k, v)| format!(":{} {}", k, v)).collect();
+                format!("{{{}}}", xs.join(", "))
             }
             FelispExp::Func(_) => "Function {}".to_string(),
             FelispExp::Bool(a) => a.to_string(),
             FelispExp::Lambda(_) => "Lambda {}".to_string(),
+            FelispExp::Macro(_) => "Macro {}".to_string(),
             FelispExp::Table(a) => {
-                format!("Table: Name: {} Rows: {}", a.name.to_string(), a.num_rows)
+                let a = a.borrow();
+                format!("Table: Name: {} Rows: {}", a.name, a.num_rows)
             }
         };
 
@@ -71,6 +344,22 @@ impl fmt::Display for FelispExp {
     }
 }
 
+// Print a string back as a literal the reader would accept
+pub fn escape_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
 /* Database layer */
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Row {
@@ -40,16 +40,200 @@ impl fmt::Display for MyResult {
 }
 
 
-pub fn execute_select(table: &mut Table) {
-    println!("Table: <{}, {} num_rows, {} num_pages>", table.name, table.num_rows, table.num_pages);
-    for page in &table.pages {
-        for row in page.iter() {
-            match row {
-                Some(row) => println!("{:?}", row),
-                None => ()
+// The rows of `table` in insertion order, page by page
+pub fn table_rows(table: &Table) -> impl Iterator<Item = &Row> {
+    table.pages.iter().flat_map(|page| page.iter().flatten())
+}
+
+// What to read from a table, built from the clauses of a `select`
+#[derive(Default)]
+pub struct Select {
+    pub filter: Option<Predicate>,
+    // Every column when not given
+    pub columns: Option<Vec<Column>>,
+    pub order_by: Option<(Column, Order)>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+// The rows matching the query, each as its selected columns and their
+// values. The filter is checked as the pages are read, and without an
+// ordering the reading stops once the offset and limit are covered.
+pub fn execute_select(table: &Table, query: &Select) -> Result<Vec<Vec<(Column, Value)>>, FelispErr> {
+    let limit = query.limit.unwrap_or(usize::MAX);
+    let wanted = match query.order_by {
+        Some(_) => usize::MAX,
+        None => query.offset.saturating_add(limit),
+    };
+    let mut rows: Vec<&Row> = vec![];
+    for row in table_rows(table) {
+        if rows.len() >= wanted {
+            break;
+        }
+        if query.filter.as_ref().map_or(Ok(true), |p| p.matches(row))? {
+            rows.push(row);
+        }
+    }
+    // sort_by is stable, so rows with equal keys keep their page order
+    // either way round
+    if let Some((col, order)) = query.order_by {
+        rows.sort_by(|a, b| match order {
+            Order::Asc => col.get(a).cmp(&col.get(b)),
+            Order::Desc => col.get(b).cmp(&col.get(a)),
+        });
+    }
+    let columns = query.columns.as_deref().unwrap_or(&Column::ALL);
+    Ok(rows
+        .into_iter()
+        .skip(query.offset)
+        .take(limit)
+        .map(|row| columns.iter().map(|col| (*col, col.get(row))).collect())
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    Id,
+    Username,
+    Email,
+}
+
+impl Column {
+    pub const ALL: [Column; 3] = [Column::Id, Column::Username, Column::Email];
+
+    pub fn from_name(name: &str) -> Option<Column> {
+        match name {
+            "id" => Some(Column::Id),
+            "username" => Some(Column::Username),
+            "email" => Some(Column::Email),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Username => "username",
+            Column::Email => "email",
+        }
+    }
+
+    pub fn get(&self, row: &Row) -> Value {
+        match self {
+            Column::Id => Value::Int(row.id as i64),
+            Column::Username => Value::Str(row.username.clone()),
+            Column::Email => Value::Str(row.email.clone()),
+        }
+    }
+}
+
+// A value in a column, to compare a row against
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// A WHERE clause, checked against one row at a time
+pub enum Predicate {
+    Compare(Column, CompareOp, Value),
+    // SQL LIKE, where `%` matches any run of characters and `_` any one
+    Like(Column, String),
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+    // Anything else, such as a felisp function of the row
+    Fn(Box<RowFn>),
+}
+
+pub type RowFn = dyn Fn(&Row) -> Result<bool, FelispErr>;
+
+impl Predicate {
+    pub fn matches(&self, row: &Row) -> Result<bool, FelispErr> {
+        Ok(match self {
+            Predicate::Compare(col, op, value) => {
+                let cell = col.get(row);
+                match op {
+                    CompareOp::Eq => cell == *value,
+                    CompareOp::Lt => cell < *value,
+                    CompareOp::Le => cell <= *value,
+                    CompareOp::Gt => cell > *value,
+                    CompareOp::Ge => cell >= *value,
+                }
+            }
+            Predicate::Like(col, pattern) => match col.get(row) {
+                Value::Str(s) => {
+                    let pattern: Vec<char> = pattern.chars().collect();
+                    let s: Vec<char> = s.chars().collect();
+                    like(&pattern, &s)
+                }
+                Value::Int(_) => false,
+            },
+            Predicate::And(preds) => {
+                for p in preds {
+                    if !p.matches(row)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Predicate::Or(preds) => {
+                for p in preds {
+                    if p.matches(row)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+            Predicate::Not(p) => !p.matches(row)?,
+            Predicate::Fn(f) => f(row)?,
+        })
+    }
+}
+
+// On a mismatch, go back to the last `%` and let it take one more char.
+// An earlier `%` never needs to take more once a later one matched, so
+// this is at most len(pattern) * len(s) steps.
+fn like(pattern: &[char], s: &[char]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // The pattern index after the last `%` seen, and where in `s` it stopped
+    let mut backtrack: Option<(usize, usize)> = None;
+    while i < s.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                backtrack = Some((p, i));
+            }
+            Some(c) if *c == '_' || *c == s[i] => {
+                p += 1;
+                i += 1;
             }
+            _ => match backtrack {
+                Some((star_p, star_i)) => {
+                    p = star_p;
+                    i = star_i + 1;
+                    backtrack = Some((star_p, i));
+                }
+                None => return false,
+            },
         }
     }
+    pattern[p..].iter().all(|c| *c == '%')
 }
 
 pub fn execute_insert(table: &mut Table, id: i32, username: String, email: String) {
@@ -58,7 +242,7 @@ pub fn execute_insert(table: &mut Table, id: i32, username: String, email: Strin
     let page_num: i32 = (table.num_rows  as i32) / (ROWS_PER_PAGE as i32);
     //println!(">> page_num: {}, num_pages: {}", page_num, table.num_pages);
 
-    if (page_num > table.num_pages) {
+    if page_num >= table.num_pages {
         // this page doesn't exist. so we
         // println!("********** this page {} doesn't exist lets create", page_num);
         // push a array of 10 rows into the rows vector
@@ -88,7 +272,7 @@ fn create_dummy_table () -> Table {
     let mut t = Table {
         name: String::from("mytable1"),
         num_rows: 0,
-        num_pages: 0,
+        num_pages: 1,
         pages: vec![xs],
     };
     for i in 0..21 {
@@ -234,6 +418,8 @@ fn read_table_from_file(filename: String) {
 mod test {
 
     use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
 
     #[test]
     fn test_dummy_table() {
@@ -268,7 +454,7 @@ mod test {
         let mut t = Table {
             name: String::from("mytable1"),
             num_rows: 0,
-            num_pages: 0,
+            num_pages: 1,
             pages: vec![xs],
         };
         for i in 0..22 {
@@ -277,13 +463,119 @@ mod test {
                            String::from(format!("apple{}", i+1)),
                            String::from(format!("apple{}@orange{}", i+1, i+1)));
         }
+        assert_eq!(t.num_rows, 22);
+        assert_eq!(t.num_pages, 3);
+        assert_eq!(t.pages.len(), 3);
+    }
 
+    fn ids(rows: Vec<Vec<(Column, Value)>>) -> Vec<i64> {
+        rows.iter()
+            .map(|row| match row[0] {
+                (Column::Id, Value::Int(id)) => id,
+                _ => panic!("expected the id first"),
+            })
+            .collect()
     }
 
     #[test]
     fn test_execute_select() {
+        let t = create_dummy_table();
+        let rows = execute_select(&t, &Select::default()).unwrap();
+        assert_eq!(rows.len(), 21);
+        assert_eq!(rows[0][1], (Column::Username, Value::Str("apple0".to_string())));
+        assert_eq!(rows[20][2], (Column::Email, Value::Str("apple20@orange20".to_string())));
+    }
+
+    #[test]
+    fn test_select_where() {
+        let t = create_dummy_table();
+        let select = |p: Predicate| {
+            let query = Select { filter: Some(p), ..Select::default() };
+            ids(execute_select(&t, &query).unwrap())
+        };
+        assert_eq!(select(Predicate::Compare(Column::Id, CompareOp::Eq, Value::Int(5))), vec![5]);
+        assert_eq!(select(Predicate::Compare(Column::Id, CompareOp::Ge, Value::Int(19))), vec![19, 20]);
+        let like1 = Predicate::Like(Column::Email, "apple1_@%".to_string());
+        assert_eq!(select(like1), vec![10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+        let either = Predicate::Or(vec![
+            Predicate::Like(Column::Username, "%2".to_string()),
+            Predicate::Compare(Column::Username, CompareOp::Eq, Value::Str("apple3".to_string())),
+        ]);
+        assert_eq!(select(either), vec![2, 3, 12]);
+        let both = Predicate::And(vec![
+            Predicate::Compare(Column::Id, CompareOp::Lt, Value::Int(4)),
+            Predicate::Not(Box::new(Predicate::Fn(Box::new(|r| Ok(r.id % 2 == 0))))),
+        ]);
+        assert_eq!(select(both), vec![1, 3]);
+        let failing = Select {
+            filter: Some(Predicate::Fn(Box::new(|_| Err(FelispErr::db("bad row"))))),
+            ..Select::default()
+        };
+        assert!(execute_select(&t, &failing).is_err());
+    }
+
+    #[test]
+    fn test_select_order_and_paging() {
         let mut t = create_dummy_table();
-        execute_select(&mut t);
+        execute_insert(&mut t, 30, "apple5".to_string(), "dup@x".to_string());
+        let desc = Select {
+            order_by: Some((Column::Username, Order::Desc)),
+            limit: Some(4),
+            ..Select::default()
+        };
+        assert_eq!(ids(execute_select(&t, &desc).unwrap()), vec![9, 8, 7, 6]);
+        let ties = Select {
+            order_by: Some((Column::Username, Order::Asc)),
+            offset: 15,
+            limit: Some(3),
+            ..Select::default()
+        };
+        assert_eq!(ids(execute_select(&t, &ties).unwrap()), vec![4, 5, 30]);
+        let ties = Select { order_by: Some((Column::Username, Order::Desc)), ..ties };
+        assert_eq!(ids(execute_select(&t, &ties).unwrap()), vec![14, 13, 12]);
+        let projected = Select {
+            columns: Some(vec![Column::Email]),
+            offset: 21,
+            ..Select::default()
+        };
+        let rows = execute_select(&t, &projected).unwrap();
+        assert_eq!(rows, vec![vec![(Column::Email, Value::Str("dup@x".to_string()))]]);
+    }
+
+    #[test]
+    fn test_select_limit_stops_early() {
+        let t = create_dummy_table();
+        let checked = Rc::new(Cell::new(0));
+        let counter = checked.clone();
+        let query = Select {
+            filter: Some(Predicate::Fn(Box::new(move |_| {
+                counter.set(counter.get() + 1);
+                Ok(true)
+            }))),
+            offset: 2,
+            limit: Some(3),
+            ..Select::default()
+        };
+        assert_eq!(ids(execute_select(&t, &query).unwrap()), vec![2, 3, 4]);
+        assert_eq!(checked.get(), 5);
+    }
+
+    #[test]
+    fn test_like() {
+        let like_str = |p: &str, s: &str| {
+            like(&p.chars().collect::<Vec<_>>(), &s.chars().collect::<Vec<_>>())
+        };
+        assert!(like_str("%@x.com", "amy@x.com"));
+        assert!(!like_str("%@x.com", "amy@x.co"));
+        assert!(like_str("a_y%", "amy"));
+        assert!(like_str("%%", ""));
+        assert!(!like_str("_", ""));
+        assert!(like_str("a%b%c", "aXbYbZc"));
+        assert!(!like_str("a%b%c", "aXbYbZ"));
+        assert!(like_str("%a_%", "xab"));
+        // Would take exponential time with naive backtracking
+        let s = "a".repeat(200);
+        assert!(!like_str(&format!("{}b", "%a".repeat(20)), &s));
     }
 
 
@@ -299,7 +591,7 @@ mod test {
         let mut t = Table {
             name: String::from("mytable1"),
             num_rows: 0,
-            num_pages: 0,
+            num_pages: 1,
             pages: vec![xs],
         };
         println!("t.rows: {:?}", t.pages[0]);
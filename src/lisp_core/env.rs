@@ -1,92 +1,520 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use num_rational::BigRational;
 
 use crate::lib::data::*;
+use crate::lisp_core::eval::is_truthy;
+use crate::lisp_core::numeric::*;
 use crate::lisp_core::parser::*;
 
-#[macro_export]
-macro_rules! ensure_tonicity {
-    ($check_fn:expr) => {{
-        |args: &[FelispExp]| -> Result<FelispExp, FelispErr> {
-            let floats = parse_list_of_floats(args)?;
-            let first = floats.first().ok_or(FelispErr::Reason(
-                "expected at least one number".to_string(),
-            ))?;
-            let rest = &floats[1..];
-            fn f(prev: &f64, xs: &[f64]) -> bool {
-                match xs.first() {
-                    Some(x) => $check_fn(prev, x) && f(x, &xs[1..]),
-                    None => true,
-                }
-            };
-            Ok(FelispExp::Bool(f(first, rest)))
+// Shorthand for registering a builtin under its own name
+pub fn define_builtin<F>(data: &mut HashMap<String, FelispExp>, name: &str, arity: Arity, func: F)
+where
+    F: Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr> + 'static,
+{
+    data.insert(name.to_string(), FelispExp::Func(FelispFunc::new(name, arity, func)));
+}
+
+// A comparison that holds when `check` holds for each pair of neighbours
+pub fn tonicity(
+    check: fn(&Num, &Num) -> bool,
+) -> impl Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr> {
+    move |args, _| {
+        let nums = parse_list_of_nums(args)?;
+        Ok(FelispExp::Bool(nums.windows(2).all(|w| check(&w[0], &w[1]))))
+    }
+}
+
+// Whether two values are the same: numbers by value, so (= 1 1.0) holds,
+// lists and maps item by item, and functions and tables only when they
+// are the very same one
+pub fn equal(a: &FelispExp, b: &FelispExp) -> bool {
+    match (a, b) {
+        (FelispExp::Nil, FelispExp::Nil) => true,
+        (FelispExp::Bool(x), FelispExp::Bool(y)) => x == y,
+        (FelispExp::Symbol(x), FelispExp::Symbol(y)) | (FelispExp::Str(x), FelispExp::Str(y)) => x == y,
+        (FelispExp::List(xs), FelispExp::List(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| equal(x, y))
+        }
+        (FelispExp::Map(xs), FelispExp::Map(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|((k, x), (l, y))| k == l && equal(x, y))
+        }
+        (FelispExp::Func(f), FelispExp::Func(g)) => Rc::ptr_eq(&f.func, &g.func),
+        (FelispExp::Lambda(f), FelispExp::Lambda(g)) | (FelispExp::Macro(f), FelispExp::Macro(g)) => {
+            Rc::ptr_eq(&f.body_exp, &g.body_exp)
         }
-    }};
+        (FelispExp::Table(x), FelispExp::Table(y)) => Rc::ptr_eq(x, y),
+        _ => match (Num::from_exp(a), Num::from_exp(b)) {
+            (Ok(x), Ok(y)) => x == y,
+            _ => false,
+        },
+    }
+}
+
+// Wrap a `fn(f64) -> f64` as a one argument builtin, these always return
+// a float
+pub fn unary_float_fn(
+    name: &'static str,
+    f: fn(f64) -> f64,
+) -> impl Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr> {
+    move |args, _| {
+        let x = Num::from_exp(&args[0])?.to_f64();
+        Ok(float_result(name, f(x))?.into_exp())
+    }
+}
+
+// Wrap a `fn(&Num) -> Result<Num, FelispErr>` as a one argument builtin
+pub fn unary_num_fn(
+    f: fn(&Num) -> Result<Num, FelispErr>,
+) -> impl Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr> {
+    move |args, _| Ok(f(&Num::from_exp(&args[0])?)?.into_exp())
+}
+
+// Wrap a `fn(&Num, &Num) -> Result<Num, FelispErr>` as a two argument builtin
+pub fn binary_num_fn(
+    f: fn(&Num, &Num) -> Result<Num, FelispErr>,
+) -> impl Fn(&[FelispExp], &mut Context) -> Result<FelispExp, FelispErr> {
+    move |args, _| {
+        let a = Num::from_exp(&args[0])?;
+        let b = Num::from_exp(&args[1])?;
+        Ok(f(&a, &b)?.into_exp())
+    }
+}
+
+pub fn parse_list_arg(arg: &FelispExp) -> Result<&[FelispExp], FelispErr> {
+    match arg {
+        FelispExp::List(list) => Ok(list),
+        other => Err(FelispErr::type_error("a list", other)),
+    }
+}
+
+pub fn parse_map_arg(arg: &FelispExp) -> Result<&BTreeMap<String, FelispExp>, FelispErr> {
+    match arg {
+        FelispExp::Map(map) => Ok(map),
+        other => Err(FelispErr::type_error("a map", other)),
+    }
+}
+
+// Map keys are written as keywords, `:id`, or as strings, "id"
+pub fn parse_map_key(arg: &FelispExp) -> Result<String, FelispErr> {
+    match arg {
+        FelispExp::Symbol(k) if k.len() > 1 && k.starts_with(':') => Ok(k[1..].to_string()),
+        FelispExp::Str(k) => Ok(k.clone()),
+        other => Err(FelispErr::type_error("a keyword or string key", other)),
+    }
+}
+
+// Fill `map` from a flat list of alternating keys and values
+// (`is_multiple_of` would need Rust 1.87)
+#[allow(clippy::manual_is_multiple_of)]
+fn insert_pairs(
+    name: &str,
+    map: &mut BTreeMap<String, FelispExp>,
+    pairs: &[FelispExp],
+) -> Result<(), FelispErr> {
+    if pairs.len() % 2 != 0 {
+        return Err(FelispErr::arity(name, "keys and values in pairs", pairs.len()));
+    }
+    for pair in pairs.chunks(2) {
+        map.insert(parse_map_key(&pair[0])?, pair[1].clone());
+    }
+    Ok(())
+}
+
+// How many symbols `gensym` has made, so that each gets a new number
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// A symbol no other symbol is equal to, unless spelled out by hand, for
+// macros to bind names without capturing the caller's
+pub fn gensym(prefix: &str) -> String {
+    format!("{}__{}", prefix, GENSYM_COUNTER.fetch_add(1, Ordering::Relaxed) + 1)
 }
 
 // Set up the environment to control functions and operators and data
-pub fn default_env<'a>() -> FelispEnv<'a> {
+pub fn default_env() -> FelispEnv {
     let mut data: HashMap<String, FelispExp> = HashMap::new();
 
     // Addition operators
-    data.insert(
-        "+".to_string(),
-        FelispExp::Func(|args: &[FelispExp]| -> Result<FelispExp, FelispErr> {
-            let sum = parse_list_of_floats(args)?
-                .iter()
-                .fold(0.0, |sum, a| sum + a);
-            Ok(FelispExp::Number(sum))
-        }),
-    );
+    define_builtin(&mut data, "+", Arity::AtLeast(0), |args, _| {
+        let mut sum = Num::Int(0);
+        for a in parse_list_of_nums(args)? {
+            sum = add(&sum, &a)?;
+        }
+        Ok(sum.into_exp())
+    });
 
-    // Subtraction operator
-    data.insert(
-        "-".to_string(),
-        FelispExp::Func(|args: &[FelispExp]| -> Result<FelispExp, FelispErr> {
-            let floats = parse_list_of_floats(args)?;
-            let first = *floats
-                .first()
-                .ok_or(FelispErr::Reason("expected atleast one number".to_string()))?;
-            let sum_of_rest = floats[1..].iter().fold(0.0, |sum, a| sum + a);
-            Ok(FelispExp::Number(first - sum_of_rest))
-        }),
-    );
+    // Subtraction operator, `(- x)` negates x
+    define_builtin(&mut data, "-", Arity::AtLeast(1), |args, _| {
+        let nums = parse_list_of_nums(args)?;
+        let (first, rest) = nums.split_first().unwrap();
+        if rest.is_empty() {
+            return Ok(neg(first)?.into_exp());
+        }
+        let mut difference = first.clone();
+        for a in rest {
+            difference = sub(&difference, a)?;
+        }
+        Ok(difference.into_exp())
+    });
 
-    data.insert(
-        "=".to_string(),
-        FelispExp::Func(ensure_tonicity!(|a, b| a == b)),
-    );
-    data.insert(
-        ">".to_string(),
-        FelispExp::Func(ensure_tonicity!(|a, b| a > b)),
+    // Multiplication operator
+    define_builtin(&mut data, "*", Arity::AtLeast(0), |args, _| {
+        let mut product = Num::Int(1);
+        for a in parse_list_of_nums(args)? {
+            product = mul(&product, &a)?;
+        }
+        Ok(product.into_exp())
+    });
+
+    // Division operator, `(/ x)` is the reciprocal of x
+    define_builtin(&mut data, "/", Arity::AtLeast(1), |args, _| {
+        let nums = parse_list_of_nums(args)?;
+        let (first, rest) = nums.split_first().unwrap();
+        if rest.is_empty() {
+            return Ok(div(&Num::Int(1), first)?.into_exp());
+        }
+        let mut quotient = first.clone();
+        for a in rest {
+            quotient = div(&quotient, a)?;
+        }
+        Ok(quotient.into_exp())
+    });
+
+    // Integer division. The remainder takes the sign of the dividend, the
+    // modulo that of the divisor
+    define_builtin(&mut data, "quot", Arity::Exactly(2), binary_num_fn(quot));
+    define_builtin(&mut data, "rem", Arity::Exactly(2), binary_num_fn(rem));
+    define_builtin(&mut data, "mod", Arity::Exactly(2), binary_num_fn(modulo));
+
+    define_builtin(&mut data, "min", Arity::AtLeast(1), |args, _| {
+        let nums = parse_list_of_nums(args)?;
+        let min = nums.iter().fold(&nums[0], |m, a| if a < m { a } else { m });
+        Ok(min.clone().into_exp())
+    });
+    define_builtin(&mut data, "max", Arity::AtLeast(1), |args, _| {
+        let nums = parse_list_of_nums(args)?;
+        let max = nums.iter().fold(&nums[0], |m, a| if a > m { a } else { m });
+        Ok(max.clone().into_exp())
+    });
+
+    // Powers and logarithms, `(log x b)` takes the log of x in base b
+    define_builtin(&mut data, "pow", Arity::Exactly(2), binary_num_fn(pow));
+    define_builtin(&mut data, "log", Arity::Between(1, 2), |args, _| {
+        let nums = parse_list_of_nums(args)?;
+        let x = nums[0].to_f64();
+        let base = nums.get(1).map_or(std::f64::consts::E, Num::to_f64);
+        if x <= 0.0 || base <= 0.0 || base == 1.0 {
+            return Err(FelispErr::arithmetic("log: argument out of domain"));
+        }
+        Ok(float_result("log", x.log(base))?.into_exp())
+    });
+    define_builtin(&mut data, "exp", Arity::Exactly(1), unary_float_fn("exp", f64::exp));
+    define_builtin(&mut data, "sqrt", Arity::Exactly(1), unary_float_fn("sqrt", f64::sqrt));
+
+    // Rounding, and conversions between integers and floats
+    define_builtin(&mut data, "abs", Arity::Exactly(1), unary_num_fn(abs));
+    define_builtin(
+        &mut data,
+        "floor",
+        Arity::Exactly(1),
+        unary_num_fn(|a| round_with("floor", a, BigRational::floor, f64::floor)),
     );
-    data.insert(
-        ">=".to_string(),
-        FelispExp::Func(ensure_tonicity!(|a, b| a >= b)),
+    define_builtin(
+        &mut data,
+        "ceil",
+        Arity::Exactly(1),
+        unary_num_fn(|a| round_with("ceil", a, BigRational::ceil, f64::ceil)),
     );
-    data.insert(
-        "<".to_string(),
-        FelispExp::Func(ensure_tonicity!(|a, b| a < b)),
+    define_builtin(
+        &mut data,
+        "round",
+        Arity::Exactly(1),
+        unary_num_fn(|a| round_with("round", a, BigRational::round, f64::round)),
     );
-    data.insert(
-        "<=".to_string(),
-        FelispExp::Func(ensure_tonicity!(|a, b| a <= b)),
+    define_builtin(
+        &mut data,
+        "truncate",
+        Arity::Exactly(1),
+        unary_num_fn(|a| round_with("truncate", a, BigRational::trunc, f64::trunc)),
     );
+    define_builtin(&mut data, "int", Arity::Exactly(1), unary_num_fn(to_int));
+    define_builtin(&mut data, "float", Arity::Exactly(1), unary_num_fn(to_float));
+
+    // Trigonometry, `(atan y x)` is the two argument arctangent
+    define_builtin(&mut data, "sin", Arity::Exactly(1), unary_float_fn("sin", f64::sin));
+    define_builtin(&mut data, "cos", Arity::Exactly(1), unary_float_fn("cos", f64::cos));
+    define_builtin(&mut data, "tan", Arity::Exactly(1), unary_float_fn("tan", f64::tan));
+    define_builtin(&mut data, "asin", Arity::Exactly(1), unary_float_fn("asin", f64::asin));
+    define_builtin(&mut data, "acos", Arity::Exactly(1), unary_float_fn("acos", f64::acos));
+    define_builtin(&mut data, "atan", Arity::Between(1, 2), |args, _| {
+        let nums = parse_list_of_nums(args)?;
+        match &nums[..] {
+            [y, x] => Ok(FelispExp::Float(y.to_f64().atan2(x.to_f64()))),
+            _ => Ok(FelispExp::Float(nums[0].to_f64().atan())),
+        }
+    });
+    data.insert("pi".to_string(), FelispExp::Float(std::f64::consts::PI));
+    data.insert("e".to_string(), FelispExp::Float(std::f64::consts::E));
+
+    define_builtin(&mut data, "=", Arity::AtLeast(1), |args, _| {
+        Ok(FelispExp::Bool(args.windows(2).all(|w| equal(&w[0], &w[1]))))
+    });
+    define_builtin(&mut data, ">", Arity::AtLeast(1), tonicity(|a, b| a > b));
+    define_builtin(&mut data, ">=", Arity::AtLeast(1), tonicity(|a, b| a >= b));
+    define_builtin(&mut data, "<", Arity::AtLeast(1), tonicity(|a, b| a < b));
+    define_builtin(&mut data, "<=", Arity::AtLeast(1), tonicity(|a, b| a <= b));
+    define_builtin(&mut data, "not", Arity::Exactly(1), |args, _| {
+        Ok(FelispExp::Bool(!is_truthy(&args[0])))
+    });
+
+    // Lists
+    define_builtin(&mut data, "list", Arity::AtLeast(0), |args, _| {
+        Ok(FelispExp::List(args.to_vec()))
+    });
+    define_builtin(&mut data, "cons", Arity::Exactly(2), |args, _| {
+        let mut res = vec![args[0].clone()];
+        res.extend_from_slice(parse_list_arg(&args[1])?);
+        Ok(FelispExp::List(res))
+    });
+    define_builtin(&mut data, "first", Arity::Exactly(1), |args, _| {
+        let list = parse_list_arg(&args[0])?;
+        Ok(list.first().cloned().unwrap_or(FelispExp::Nil))
+    });
+    define_builtin(&mut data, "rest", Arity::Exactly(1), |args, _| {
+        let list = parse_list_arg(&args[0])?;
+        Ok(FelispExp::List(list.iter().skip(1).cloned().collect()))
+    });
+    define_builtin(&mut data, "count", Arity::Exactly(1), |args, _| {
+        let list = parse_list_arg(&args[0])?;
+        Ok(FelispExp::Int(list.len() as i64))
+    });
+    define_builtin(&mut data, "empty?", Arity::Exactly(1), |args, _| {
+        let list = parse_list_arg(&args[0])?;
+        Ok(FelispExp::Bool(list.is_empty()))
+    });
+
+    // Maps, `(hash-map :id 1 :name "bob")`
+    define_builtin(&mut data, "hash-map", Arity::AtLeast(0), |args, _| {
+        let mut map = BTreeMap::new();
+        insert_pairs("hash-map", &mut map, args)?;
+        Ok(FelispExp::Map(map))
+    });
+    // (get m :k) is nil for a missing key, (get m :k default) is default
+    define_builtin(&mut data, "get", Arity::Between(2, 3), |args, _| {
+        let map = parse_map_arg(&args[0])?;
+        let key = parse_map_key(&args[1])?;
+        let default = args.get(2).cloned().unwrap_or(FelispExp::Nil);
+        Ok(map.get(&key).cloned().unwrap_or(default))
+    });
+    define_builtin(&mut data, "assoc", Arity::AtLeast(3), |args, _| {
+        let mut map = parse_map_arg(&args[0])?.clone();
+        insert_pairs("assoc", &mut map, &args[1..])?;
+        Ok(FelispExp::Map(map))
+    });
+    define_builtin(&mut data, "dissoc", Arity::AtLeast(1), |args, _| {
+        let mut map = parse_map_arg(&args[0])?.clone();
+        for key in &args[1..] {
+            map.remove(&parse_map_key(key)?);
+        }
+        Ok(FelispExp::Map(map))
+    });
+    define_builtin(&mut data, "contains?", Arity::Exactly(2), |args, _| {
+        let map = parse_map_arg(&args[0])?;
+        Ok(FelispExp::Bool(map.contains_key(&parse_map_key(&args[1])?)))
+    });
+    define_builtin(&mut data, "keys", Arity::Exactly(1), |args, _| {
+        let map = parse_map_arg(&args[0])?;
+        let keys = map.keys().map(|k| FelispExp::Symbol(format!(":{}", k)));
+        Ok(FelispExp::List(keys.collect()))
+    });
+    define_builtin(&mut data, "vals", Arity::Exactly(1), |args, _| {
+        let map = parse_map_arg(&args[0])?;
+        Ok(FelispExp::List(map.values().cloned().collect()))
+    });
+
+    // Higher order functions, which call back into the evaluator through
+    // the context. `map` walks several lists side by side, stopping at the
+    // end of the shortest.
+    define_builtin(&mut data, "map", Arity::AtLeast(2), |args, ctx| {
+        let lists = args[1..]
+            .iter()
+            .map(|arg| parse_list_arg(arg))
+            .collect::<Result<Vec<&[FelispExp]>, FelispErr>>()?;
+        let len = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+        let mut res = vec![];
+        for i in 0..len {
+            let call_args = lists.iter().map(|list| list[i].clone()).collect();
+            res.push(ctx.apply(&args[0], call_args)?);
+        }
+        Ok(FelispExp::List(res))
+    });
+    define_builtin(&mut data, "filter", Arity::Exactly(2), |args, ctx| {
+        let mut res = vec![];
+        for x in parse_list_arg(&args[1])? {
+            if is_truthy(&ctx.apply(&args[0], vec![x.clone()])?) {
+                res.push(x.clone());
+            }
+        }
+        Ok(FelispExp::List(res))
+    });
+    // (reduce f xs) starts from the first element, (reduce f init xs) from init
+    define_builtin(&mut data, "reduce", Arity::Between(2, 3), |args, ctx| {
+        let list = parse_list_arg(&args[args.len() - 1])?;
+        let (mut acc, rest) = match args {
+            [_, init, _] => (init.clone(), list),
+            _ => match list.split_first() {
+                Some((first, rest)) => (first.clone(), rest),
+                None => {
+                    let expected = "a non-empty list or an initial value";
+                    return Err(FelispErr::type_error(expected, &args[1]));
+                }
+            },
+        };
+        for x in rest {
+            acc = ctx.apply(&args[0], vec![acc, x.clone()])?;
+        }
+        Ok(acc)
+    });
+    // (apply f a b '(c d)) calls f with a, b, c and d
+    define_builtin(&mut data, "apply", Arity::AtLeast(2), |args, ctx| {
+        let (last, init) = args[1..].split_last().unwrap();
+        let mut call_args = init.to_vec();
+        call_args.extend_from_slice(parse_list_arg(last)?);
+        ctx.apply(&args[0], call_args)
+    });
+
+    define_builtin(&mut data, "eval", Arity::Exactly(1), |args, ctx| ctx.eval(&args[0]));
+
+    define_builtin(&mut data, "gensym", Arity::Between(0, 1), |args, _| {
+        match args.first() {
+            None => Ok(FelispExp::Symbol(gensym("G"))),
+            Some(FelispExp::Str(prefix)) | Some(FelispExp::Symbol(prefix)) => {
+                Ok(FelispExp::Symbol(gensym(prefix)))
+            }
+            Some(other) => Err(FelispErr::type_error("a string prefix", other)),
+        }
+    });
 
     // Database layer
-    let mut rows: Vec<Row> = Vec::new(); // or Vec::new()
-    let mut t = Table {
+    let first_page: [Option<Row>; ROWS_PER_PAGE] = Default::default();
+    let t = Table {
         name: String::from("mytable1"),
         num_rows: 0,
-        num_pages: 0,
-        pages: vec![],
+        num_pages: 1,
+        pages: vec![first_page],
     };
 
-    data.insert("mytable1".to_string(), FelispExp::Table(t));
+    data.insert("mytable1".to_string(), FelispExp::Table(Rc::new(RefCell::new(t))));
 
     FelispEnv { data, outer: None } // Return expression
 }
 
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::lisp_core::tokenizer::tokenize;
+
+    fn call(src: &str) -> String {
+        let env = default_env();
+        let tokens = tokenize(src.to_string()).unwrap();
+        let (exp, _) = parse(&tokens).unwrap();
+        let (name, args) = match &exp {
+            FelispExp::List(list) => (list[0].to_string(), &list[1..]),
+            _ => panic!("expected a call"),
+        };
+        let mut ctx = Context {
+            env: Rc::new(RefCell::new(env.clone())),
+        };
+        match env.data.get(&name) {
+            Some(FelispExp::Func(f)) => match f.call(args, &mut ctx) {
+                Ok(res) => res.to_string(),
+                Err(err) => err.message(),
+            },
+            _ => format!("no builtin {}", name),
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(call("(+ 1 2 3)"), "6");
+        assert_eq!(call("(+ 1 2.5)"), "3.5");
+        assert_eq!(call("(- 5)"), "-5");
+        assert_eq!(call("(- 10 1 2)"), "7");
+        assert_eq!(call("(* 2 3 4)"), "24");
+        assert_eq!(call("(*)"), "1");
+        assert_eq!(call("(/ 6 3)"), "2");
+        assert_eq!(call("(/ 7 2)"), "7/2");
+        assert_eq!(call("(/ 4)"), "1/4");
+        assert_eq!(call("(+ 1/10 2/10)"), "3/10");
+        assert_eq!(call("(* 1/3 3)"), "1");
+        assert_eq!(call("(/ 6.0 3)"), "2.0");
+        assert_eq!(call("(quot 7 2)"), "3");
+        assert_eq!(call("(mod -7 3)"), "2");
+        assert_eq!(call("(rem -7 3)"), "-1");
+        assert_eq!(call("(mod -7.5 2)"), "0.5");
+        assert_eq!(call("(min 3 -1 2)"), "-1");
+        assert_eq!(call("(max 3 -1 2.5)"), "3");
+        assert_eq!(call("(abs -2.5)"), "2.5");
+        assert_eq!(call("(floor -2.5)"), "-3.0");
+        assert_eq!(call("(floor 7)"), "7");
+        assert_eq!(call("(floor 7/2)"), "3");
+        assert_eq!(call("(round 2.5)"), "3.0");
+        assert_eq!(call("(int 2.9)"), "2");
+        assert_eq!(call("(float 2)"), "2.0");
+        assert_eq!(call("(pow 2 10)"), "1024");
+        assert_eq!(call("(pow 2 -1)"), "1/2");
+        assert_eq!(call("(pow 2 64)"), "18446744073709551616");
+        assert_eq!(call("(sqrt 9)"), "3.0");
+        assert_eq!(call("(log 8 2)"), "3.0");
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(call("(= 1 1.0)"), "true");
+        assert_eq!(call("(= 1/2 0.5 2/4)"), "true");
+        assert_eq!(call("(< 9223372036854775807 9223372036854775808)"), "true");
+        assert_eq!(call("(< 1 1.5 2)"), "true");
+        assert_eq!(call("(>= 3 3 1)"), "true");
+        assert_eq!(call("(> 1 2)"), "false");
+        assert_eq!(call("(= \"a\" \"a\")"), "true");
+        assert_eq!(call("(= \"1\" 1)"), "false");
+        assert_eq!(call("(= (1 (b \"c\")) (1.0 (b \"c\")))"), "true");
+        assert_eq!(call("(= (b) (\"b\"))"), "false");
+        assert_eq!(call("(= nil false)"), "false");
+    }
+
+    #[test]
+    fn test_math_errors() {
+        assert_eq!(call("(/ 1 0)"), "/: divide by zero");
+        assert_eq!(call("(/ 1.0 0.0)"), "/: divide by zero");
+        assert_eq!(call("(/ 0)"), "/: divide by zero");
+        assert_eq!(call("(mod 1 0)"), "mod: divide by zero");
+        assert_eq!(call("(quot 1 0)"), "quot: divide by zero");
+        assert_eq!(call("(pow 0 -1)"), "pow: divide by zero");
+        assert_eq!(call("(sqrt -1)"), "sqrt: argument out of domain");
+        assert_eq!(call("(log 0)"), "log: argument out of domain");
+        assert_eq!(call("(asin 2)"), "asin: argument out of domain");
+        assert_eq!(call("(exp 1000)"), "exp: result out of range");
+        assert_eq!(call("(* 1e300 1e300)"), "*: result out of range");
+        assert_eq!(call("(sqrt 1 2)"), "sqrt expected 1 argument, got 2");
+    }
+
+    #[test]
+    fn test_arity() {
+        assert_eq!(call("(pow 1)"), "pow expected 2 arguments, got 1");
+        assert_eq!(call("(-)"), "- expected at least 1 argument, got 0");
+        assert_eq!(call("(log 1 2 3)"), "log expected 1 to 2 arguments, got 3");
+        assert_eq!(call("(gensym a b)"), "gensym expected at most 1 argument, got 2");
+        assert_eq!(call("(< 1)"), "true");
+    }
+}
+
 #[test]
 fn test_env_cccc() {
     assert_eq!(2, 2);
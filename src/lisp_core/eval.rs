@@ -1,197 +1,1105 @@
 use std::rc::Rc;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::process;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use crate::lib::convert::IntoFelisp;
 use crate::lib::data::*;
 use crate::lisp_core::parser::*;
-use crate::lib::db::stmt::{execute_insert, execute_select};
+use crate::lib::db::stmt::{
+    execute_insert, execute_select, table_rows, Column, CompareOp, Order, Predicate, Select,
+    Value,
+};
+use crate::lisp_core::env::gensym;
 
-pub fn eval_if_args(arg_forms: &[FelispExp], env: &mut FelispEnv) -> Result<FelispExp, FelispErr> {
+// What is left to do after a step of evaluation: either the value is
+// known, or it is the value of another form, which `eval` then evaluates
+// in its own loop instead of growing the Rust stack. A form in tail
+// position is borrowed from the form being evaluated, so the loop can
+// evaluate it without copying.
+pub enum FelispTail<'a> {
+    Value(FelispExp),
+    Eval(&'a FelispExp, FelispEnvRef),
+    // What a macro call expanded to, in the same frame
+    Expansion(Rc<FelispExp>, FelispEnvRef),
+    // The body of the named lambda, in its new frame
+    Call(Rc<FelispExp>, FelispEnvRef, String),
+}
+
+// Set by `interrupt` and checked between steps of evaluation
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Stop the evaluation in progress, if any, with an `Interrupted` error.
+// Only sets a flag, so it is safe to call from a signal handler.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Forget an interrupt that came while nothing was being evaluated, such as
+// a Ctrl-C at an idle prompt
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+// Only `false` and `nil` are falsy, every other value counts as true
+pub fn is_truthy(exp: &FelispExp) -> bool {
+    !matches!(exp, FelispExp::Nil | FelispExp::Bool(false))
+}
+
+// (if test then [else]), a missing else branch makes the value nil
+pub fn eval_if_args<'a>(
+    arg_forms: &'a [FelispExp],
+    env: &FelispEnvRef,
+) -> Result<FelispTail<'a>, FelispErr> {
     let test_form = arg_forms
         .first()
-        .ok_or(FelispErr::Reason("expected test form".to_string()))?;
-    let test_eval = eval(test_form, env)?;
-    match test_eval {
-        FelispExp::Bool(b) => {
-            let form_idx = if b { 1 } else { 2 };
-            let res_form = arg_forms
-                .get(form_idx)
-                .ok_or(FelispErr::Reason(format!("expected form idx={}", form_idx)))?;
-            let res_eval = eval(res_form, env);
-
-            res_eval
-        }
-        _ => Err(FelispErr::Reason(format!(
-            "unexpected test form='{}'",
-            test_form.to_string()
-        ))),
+        .ok_or(FelispErr::syntax("expected test form".to_string()))?;
+    if arg_forms.len() < 2 || arg_forms.len() > 3 {
+        return Err(FelispErr::syntax(
+            "if expects a test, a then form and an optional else form".to_string(),
+        ));
+    }
+    let form_idx = if is_truthy(&eval(test_form, env)?) { 1 } else { 2 };
+    match arg_forms.get(form_idx) {
+        Some(res_form) => Ok(FelispTail::Eval(res_form, env.clone())),
+        None => Ok(FelispTail::Value(FelispExp::Nil)),
+    }
+}
+
+// (cond (test body...) ... (else body...)), the body of the first clause
+// whose test is truthy is evaluated, a clause without a body yields the
+// test value itself, and nil if no clause matches
+pub fn eval_cond_args<'a>(
+    arg_forms: &'a [FelispExp],
+    env: &FelispEnvRef,
+) -> Result<FelispTail<'a>, FelispErr> {
+    for (i, clause) in arg_forms.iter().enumerate() {
+        let clause_forms = match clause {
+            FelispExp::List(forms) if !forms.is_empty() => forms,
+            _ => {
+                return Err(FelispErr::syntax(format!(
+                    "expected a cond clause of a test and a body, got {}",
+                    clause
+                )))
+            }
+        };
+        let (test_form, body_forms) = clause_forms.split_first().unwrap();
+        let test_value = match test_form {
+            FelispExp::Symbol(s) if s == "else" => {
+                if i + 1 != arg_forms.len() {
+                    return Err(FelispErr::syntax(
+                        "else must be the last cond clause".to_string(),
+                    ));
+                }
+                FelispExp::Bool(true)
+            }
+            _ => eval(test_form, env)?,
+        };
+        if is_truthy(&test_value) {
+            if body_forms.is_empty() {
+                return Ok(FelispTail::Value(test_value));
+            }
+            return eval_do_args(body_forms, env);
+        }
     }
+    Ok(FelispTail::Value(FelispExp::Nil))
 }
 
-pub fn eval_defn_args(arg_forms: &[FelispExp], env: &mut FelispEnv) -> Result<FelispExp, FelispErr> {
+// (when test body...) runs the body if the test is truthy, `unless` if it
+// is not, otherwise the value is nil
+pub fn eval_when_args<'a>(
+    kind: &str,
+    arg_forms: &'a [FelispExp],
+    env: &FelispEnvRef,
+) -> Result<FelispTail<'a>, FelispErr> {
+    let (test_form, body_forms) = arg_forms
+        .split_first()
+        .ok_or(FelispErr::syntax("expected test form".to_string()))?;
+    if is_truthy(&eval(test_form, env)?) == (kind == "when") {
+        eval_do_args(body_forms, env)
+    } else {
+        Ok(FelispTail::Value(FelispExp::Nil))
+    }
+}
+
+// `and` stops at the first falsy value and `or` at the first truthy one,
+// returning it as is, otherwise the last form is left in tail position.
+// With no forms `and` is true and `or` is nil.
+pub fn eval_and_or_args<'a>(
+    kind: &str,
+    arg_forms: &'a [FelispExp],
+    env: &FelispEnvRef,
+) -> Result<FelispTail<'a>, FelispErr> {
+    let (last_form, init_forms) = match arg_forms.split_last() {
+        Some(forms) => forms,
+        None if kind == "and" => return Ok(FelispTail::Value(FelispExp::Bool(true))),
+        None => return Ok(FelispTail::Value(FelispExp::Nil)),
+    };
+    for form in init_forms {
+        let value = eval(form, env)?;
+        if is_truthy(&value) != (kind == "and") {
+            return Ok(FelispTail::Value(value));
+        }
+    }
+    Ok(FelispTail::Eval(last_form, env.clone()))
+}
+
+pub fn eval_defn_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
     let first_form = arg_forms
         .first()
-        .ok_or(FelispErr::Reason("expected first form".to_string()))?;
+        .ok_or(FelispErr::syntax("expected first form".to_string()))?;
     let first_str = match first_form {
         FelispExp::Symbol(s) => Ok(s.clone()),
-        _ => Err(FelispErr::Reason(
+        _ => Err(FelispErr::syntax(
             "expected first form to be a symbol".to_string(),
         )),
     }?;
+    check_not_special_form(&first_str)?;
     let second_form = arg_forms
         .get(1)
-        .ok_or(FelispErr::Reason("expected second form".to_string()))?;
+        .ok_or(FelispErr::syntax("expected second form".to_string()))?;
     if arg_forms.len() > 2 {
-        return Err(FelispErr::Reason(
+        return Err(FelispErr::syntax(
             "depub fn can only have two forms ".to_string(),
         ));
     }
-    let second_eval = eval(second_form, env)?;
-    env.data.insert(first_str, second_eval);
+    let second_eval = match eval(second_form, env)? {
+        FelispExp::Lambda(lambda) if lambda.name.is_none() => FelispExp::Lambda(FelispLambda {
+            name: Some(first_str.clone()),
+            ..lambda
+        }),
+        value => value,
+    };
+    env.borrow_mut().data.insert(first_str, second_eval);
 
     Ok(first_form.clone())
 }
 
-pub fn eval_select_args(arg_forms: &[FelispExp], env: &mut FelispEnv) -> Result<FelispExp, FelispErr> {
-    let first_form = arg_forms
-        .first()
-        .ok_or(FelispErr::Reason("expected first form".to_string()))?;
-    println!(">> Called Select");
-    let mut t = eval(first_form, env)?;
-    println!(">> selected t is {} ", t);
-    match t {
-        FelispExp::Table(mut t) => {
-            execute_select(&mut t);
+// (defmacro name (params) body...) binds a macro in the current frame
+pub fn eval_defmacro_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    let name = match arg_forms.first() {
+        Some(FelispExp::Symbol(s)) => s.clone(),
+        Some(_) => {
+            return Err(FelispErr::syntax(
+                "expected first form to be a symbol".to_string(),
+            ))
         }
-        _ => {}
+        None => return Err(FelispErr::syntax("expected first form".to_string())),
+    };
+    check_not_special_form(&name)?;
+    let lambda = match eval_lambda_args(&arg_forms[1..], env)? {
+        FelispExp::Lambda(lambda) => lambda,
+        _ => unreachable!(),
+    };
+    env.borrow_mut().data.insert(name, FelispExp::Macro(lambda));
+    Ok(arg_forms[0].clone())
+}
+
+// A macro called `if` would be expanded before `if` itself is looked at
+fn check_not_special_form(name: &str) -> Result<(), FelispErr> {
+    if SPECIAL_FORMS.contains(&name) {
+        return Err(FelispErr::syntax(format!("cannot redefine special form {}", name)));
     }
-    Ok(first_form.clone())
+    Ok(())
 }
 
-pub fn eval_insert_args(arg_forms: &[FelispExp], env: &mut FelispEnv) -> Result<FelispExp, FelispErr> {
+pub fn eval_set_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
     let first_form = arg_forms
         .first()
-        .ok_or(FelispErr::Reason("expected first form".to_string()))?;
+        .ok_or(FelispErr::syntax("expected first form".to_string()))?;
+    let first_str = match first_form {
+        FelispExp::Symbol(s) => Ok(s.clone()),
+        _ => Err(FelispErr::syntax(
+            "expected first form to be a symbol".to_string(),
+        )),
+    }?;
     let second_form = arg_forms
         .get(1)
-        .ok_or(FelispErr::Reason("expected second form".to_string()))?;
-    let third_form = arg_forms
-        .get(2)
-        .ok_or(FelispErr::Reason("expected second form".to_string()))?;
-    println!(
-        "Called insert [{} {} {}]",
-        first_form, second_form, third_form
-    );
-    let mut t = eval(first_form, env)?;
-    println!(">> insert t is {} ", t);
-    match t {
-        FelispExp::Table(mut t) => {
-            println!("+++ here");
-            execute_insert(&mut t, 1, second_form.to_string(), third_form.to_string());
-            execute_select(&mut t);
-            env.data.insert(first_form.to_string(), FelispExp::Table(t));
+        .ok_or(FelispErr::syntax("expected second form".to_string()))?;
+    if arg_forms.len() > 2 {
+        return Err(FelispErr::syntax(
+            "set! can only have two forms".to_string(),
+        ));
+    }
+    let second_eval = eval(second_form, env)?;
+    if !env_set(&first_str, second_eval.clone(), env) {
+        return Err(FelispErr::unbound(&first_str));
+    }
+    Ok(second_eval)
+}
+
+// (select table) evaluates to the table's rows, as a list of maps with
+// :id, :username and :email. Clauses after the table narrow it down:
+//   :where pred             only the rows that pred holds for
+//   :columns (id email)     only these keys in each map
+//   :order-by (email :desc) sorted by a column, :asc by default
+//   :limit n and :offset n  a page of the result
+pub fn eval_select_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    Arity::AtLeast(1).check("select", arg_forms.len())?;
+    let table = match eval(&arg_forms[0], env)? {
+        FelispExp::Table(t) => t,
+        other => return Err(FelispErr::type_error("a table", &other)),
+    };
+    let mut query = Select::default();
+    for clause in arg_forms[1..].chunks(2) {
+        let (k, form) = match clause {
+            [FelispExp::Symbol(k), form] => (k.as_str(), form),
+            [k, _] => return Err(FelispErr::syntax(format!("unknown select clause {}", k))),
+            _ => {
+                return Err(FelispErr::syntax(format!(
+                    "expected a value after {}",
+                    clause[0]
+                )))
+            }
+        };
+        match k {
+            ":where" => query.filter = Some(parse_predicate(form, env)?),
+            ":columns" => query.columns = Some(parse_columns(form)?),
+            ":order-by" => query.order_by = Some(parse_order_by(form)?),
+            ":limit" => query.limit = Some(parse_count(&eval(form, env)?)?),
+            ":offset" => query.offset = parse_count(&eval(form, env)?)?,
+            _ => return Err(FelispErr::syntax(format!("unknown select clause {}", k))),
         }
-        _ => {
-            println!("----> here");
+    }
+    let rows = execute_select(&table.borrow(), &query)?
+        .into_iter()
+        .map(|cells| {
+            let map = cells.into_iter().map(|(col, value)| {
+                let value = match value {
+                    Value::Int(n) => FelispExp::Int(n),
+                    Value::Str(s) => FelispExp::Str(s),
+                };
+                (col.name().to_string(), value)
+            });
+            FelispExp::Map(map.collect())
+        })
+        .collect();
+    Ok(FelispExp::List(rows))
+}
+
+fn parse_columns(form: &FelispExp) -> Result<Vec<Column>, FelispErr> {
+    match form {
+        FelispExp::List(cols) => cols
+            .iter()
+            .map(|col| match col {
+                FelispExp::Symbol(name) => parse_column(name),
+                _ => Err(FelispErr::type_error("a column name", col)),
+            })
+            .collect(),
+        _ => Err(FelispErr::type_error("a list of columns", form)),
+    }
+}
+
+// `col`, `(col)`, `(col :asc)` or `(col :desc)`
+fn parse_order_by(form: &FelispExp) -> Result<(Column, Order), FelispErr> {
+    let malformed = || FelispErr::syntax(format!("expected (column :asc|:desc), got {}", form));
+    let (col, order) = match form {
+        FelispExp::Symbol(col) => (col, None),
+        FelispExp::List(list) => match &list[..] {
+            [FelispExp::Symbol(col)] => (col, None),
+            [FelispExp::Symbol(col), FelispExp::Symbol(order)] => (col, Some(order.as_str())),
+            _ => return Err(malformed()),
+        },
+        _ => return Err(malformed()),
+    };
+    let order = match order {
+        None | Some(":asc") => Order::Asc,
+        Some(":desc") => Order::Desc,
+        Some(other) => {
+            return Err(FelispErr::syntax(format!("expected :asc or :desc, got {}", other)))
         }
+    };
+    Ok((parse_column(col)?, order))
+}
+
+fn parse_count(exp: &FelispExp) -> Result<usize, FelispErr> {
+    match exp {
+        FelispExp::Int(n) => usize::try_from(*n).map_err(|_| FelispErr::type_error("a count", exp)),
+        _ => Err(FelispErr::type_error("a count", exp)),
     }
+}
 
-    Ok(first_form.clone())
+// Compile a :where form. Comparisons and `like` of a column against a
+// value, and `and`, `or` and `not` of those, are run by the storage layer
+// itself, when the symbol names a column. Any other form is evaluated, and
+// should give a boolean, nil or a function of the row map.
+fn parse_predicate(form: &FelispExp, env: &FelispEnvRef) -> Result<Predicate, FelispErr> {
+    if let FelispExp::List(list) = form {
+        if let Some(FelispExp::Symbol(head)) = list.first() {
+            let args = &list[1..];
+            let op = match head.as_str() {
+                "=" => Some(CompareOp::Eq),
+                "<" => Some(CompareOp::Lt),
+                "<=" => Some(CompareOp::Le),
+                ">" => Some(CompareOp::Gt),
+                ">=" => Some(CompareOp::Ge),
+                _ => None,
+            };
+            match (head.as_str(), args) {
+                ("and", _) | ("or", _) => {
+                    let preds = args
+                        .iter()
+                        .map(|arg| parse_predicate(arg, env))
+                        .collect::<Result<Vec<Predicate>, FelispErr>>()?;
+                    return Ok(if head == "and" {
+                        Predicate::And(preds)
+                    } else {
+                        Predicate::Or(preds)
+                    });
+                }
+                ("not", [arg]) => return Ok(Predicate::Not(Box::new(parse_predicate(arg, env)?))),
+                ("like", [FelispExp::Symbol(name), pattern]) if Column::from_name(name).is_some() => {
+                    let col = parse_column(name)?;
+                    if col == Column::Id {
+                        let name = FelispExp::Symbol(name.clone());
+                        return Err(FelispErr::type_error("a string column", &name));
+                    }
+                    let pattern = parse_single_string(&eval(pattern, env)?)?;
+                    return Ok(Predicate::Like(col, pattern));
+                }
+                (_, [FelispExp::Symbol(col), value])
+                    if op.is_some() && Column::from_name(col).is_some() =>
+                {
+                    let col = parse_column(col)?;
+                    let value = match (col, eval(value, env)?) {
+                        (Column::Id, FelispExp::Int(n)) => Value::Int(n),
+                        (Column::Id, other) => return Err(FelispErr::type_error("an integer", &other)),
+                        (_, other) => Value::Str(parse_single_string(&other)?),
+                    };
+                    return Ok(Predicate::Compare(col, op.unwrap(), value));
+                }
+                _ => (),
+            }
+        }
+    }
+    let f = eval(form, env)?;
+    if matches!(f, FelispExp::Nil | FelispExp::Bool(_)) {
+        let b = is_truthy(&f);
+        return Ok(Predicate::Fn(Box::new(move |_| Ok(b))));
+    }
+    if !matches!(f, FelispExp::Func(_) | FelispExp::Lambda(_)) {
+        return Err(FelispErr::type_error("a predicate", &f));
+    }
+    let env = env.clone();
+    Ok(Predicate::Fn(Box::new(move |row| {
+        let mut ctx = Context { env: env.clone() };
+        ctx.apply(&f, vec![row.clone().into_felisp()])
+            .map(|res| is_truthy(&res))
+    })))
 }
 
-pub fn eval_exit_args(arg_forms: &[FelispExp], env: &mut FelispEnv) -> Result<FelispExp, FelispErr> {
-    println!("Called exit");
-    process::exit(0x0100);
+fn parse_column(name: &str) -> Result<Column, FelispErr> {
+    Column::from_name(name).ok_or_else(|| FelispErr::db(format!("no column named {}", name)))
 }
 
-pub fn eval_lambda_args(arg_forms: &[FelispExp]) -> Result<FelispExp, FelispErr> {
-    let params_exp = arg_forms
+// (insert table [id] username email), without an explicit id the row
+// gets the one after the largest id in the table, and an explicit id must
+// not be taken already. The row may also be
+// given as a single list, as in (insert table '(5 "bob" "bob@x.com")).
+// Evaluates to the new row, as `select` would return it.
+pub fn eval_insert_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    let first_form = arg_forms
         .first()
-        .ok_or(FelispErr::Reason("expected args form".to_string()))?;
-    let body_exp = arg_forms
-        .get(1)
-        .ok_or(FelispErr::Reason("expected second form".to_string()))?;
+        .ok_or(FelispErr::syntax("expected first form".to_string()))?;
+    let row_values = match &arg_forms[1..] {
+        [row_form] => match eval(row_form, env)? {
+            FelispExp::List(values) => values,
+            other => {
+                return Err(FelispErr::type_error("a row as a list", &other))
+            }
+        },
+        row_forms => eval_forms(row_forms, env)?,
+    };
+    let (id, username, email) = match &row_values[..] {
+        [id, username, email] => (Some(id), username, email),
+        [username, email] => (None, username, email),
+        _ => {
+            return Err(FelispErr::syntax(
+                "insert expects a table, an optional id, a username and an email".to_string(),
+            ))
+        }
+    };
+    let id = match id {
+        Some(id) => {
+            let id = parse_single_int(id)?;
+            Some(i32::try_from(id).map_err(|_| {
+                FelispErr::db(format!("row id {} out of range", id))
+            })?)
+        }
+        None => None,
+    };
+    let username = parse_single_string(username)?;
+    let email = parse_single_string(email)?;
+    let table = match eval(first_form, env)? {
+        FelispExp::Table(t) => t,
+        other => return Err(FelispErr::type_error("a table", &other)),
+    };
+    // Only fails when called from a :where of a select on the same table
+    let mut t = table
+        .try_borrow_mut()
+        .map_err(|_| FelispErr::db(format!("{} is being read", first_form)))?;
+    let id = match id {
+        Some(id) if table_rows(&t).any(|row| row.id == id) => {
+            return Err(FelispErr::db(format!("duplicate row id {}", id)))
+        }
+        Some(id) => id,
+        None => table_rows(&t)
+            .map(|row| row.id)
+            .max()
+            .unwrap_or(0)
+            .checked_add(1)
+            .ok_or_else(|| FelispErr::db("no row id left to give".to_string()))?,
+    };
+    let row = Row { id, username, email };
+    execute_insert(&mut t, row.id, row.username.clone(), row.email.clone());
+    Ok(row.into_felisp())
+}
 
-    if arg_forms.len() > 2 {
-        return Err(FelispErr::Reason(
-            "pub fn definition can only have two forms".to_string(),
-        ));
+// (exit) or (exit code) unwinds to the host, which exits with the status
+// once it has cleaned up
+pub fn eval_exit_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    Arity::Between(0, 1).check("exit", arg_forms.len())?;
+    let code = match arg_forms.first() {
+        Some(form) => match eval(form, env)? {
+            // Anything else would be cut down to its low byte by the OS
+            FelispExp::Int(n) if (0..=255).contains(&n) => n as i32,
+            other => return Err(FelispErr::type_error("an exit status", &other)),
+        },
+        None => 0,
+    };
+    Err(FelispErr::new(FelispErrKind::Exit(code)))
+}
+
+pub fn eval_lambda_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    let params_exp = arg_forms
+        .first()
+        .ok_or(FelispErr::syntax("expected args form".to_string()))?;
+    // As in `let`, a name may only be bound once
+    if let FelispExp::List(params) = params_exp {
+        for (i, param) in params.iter().enumerate() {
+            if let FelispExp::Symbol(name) = param {
+                let bound = |other: &FelispExp| matches!(other, FelispExp::Symbol(o) if o == name);
+                if name != "&" && params[..i].iter().any(bound) {
+                    return Err(FelispErr::syntax(format!(
+                        "fn binds k='{}' more than once",
+                        name
+                    )));
+                }
+            }
+        }
     }
+    // A body of several forms runs them in order, as if wrapped in a `do`
+    let body_exp = match &arg_forms[1..] {
+        [] => return Err(FelispErr::syntax("expected second form".to_string())),
+        [body_exp] => body_exp.clone(),
+        body_forms => {
+            let mut do_form = vec![FelispExp::Symbol("do".to_string())];
+            do_form.extend_from_slice(body_forms);
+            FelispExp::List(do_form)
+        }
+    };
 
     Ok(FelispExp::Lambda(FelispLambda {
-        body_exp: Rc::new(body_exp.clone()),
+        name: None,
+        body_exp: Rc::new(body_exp),
         params_exp: Rc::new(params_exp.clone()),
+        env: env.clone(),
     }))
 }
 
-pub fn eval_built_in_form(
-    exp: &FelispExp,
+pub fn eval_quote_args(arg_forms: &[FelispExp]) -> Result<FelispExp, FelispErr> {
+    match arg_forms {
+        [form] => Ok(form.clone()),
+        _ => Err(FelispErr::syntax(format!(
+            "quote expects one form, got {}",
+            arg_forms.len()
+        ))),
+    }
+}
+
+pub fn eval_quasiquote_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    match arg_forms {
+        [form] => quasiquote(form, 1, env, &mut HashMap::new()),
+        _ => Err(FelispErr::syntax(format!(
+            "quasiquote expects one form, got {}",
+            arg_forms.len()
+        ))),
+    }
+}
+
+// Is `form` a `(name x)` list, as produced by the reader prefixes
+fn prefixed_form<'a>(name: &str, form: &'a FelispExp) -> Option<&'a FelispExp> {
+    match form {
+        FelispExp::List(list) => match &list[..] {
+            [FelispExp::Symbol(s), x] if s == name => Some(x),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Copy a quasiquoted template, evaluating what is unquoted at `depth` 1.
+// Each nested quasiquote goes one level deeper and each unquote one level
+// back out, so inner templates are left for their own evaluation.
+// A symbol ending in `#`, like `tmp#`, is replaced by the same gensym
+// everywhere it appears in the template.
+fn quasiquote(
+    form: &FelispExp,
+    depth: usize,
+    env: &FelispEnvRef,
+    gensyms: &mut HashMap<String, String>,
+) -> Result<FelispExp, FelispErr> {
+    let wrap = |name: &str, x: FelispExp| {
+        FelispExp::List(vec![FelispExp::Symbol(name.to_string()), x])
+    };
+    if let Some(x) = prefixed_form("unquote", form) {
+        return match depth {
+            1 => eval(x, env),
+            _ => Ok(wrap("unquote", quasiquote(x, depth - 1, env, gensyms)?)),
+        };
+    }
+    if let Some(x) = prefixed_form("quasiquote", form) {
+        return Ok(wrap("quasiquote", quasiquote(x, depth + 1, env, gensyms)?));
+    }
+    if let Some(x) = prefixed_form("unquote-splicing", form) {
+        return match depth {
+            1 => Err(FelispErr::syntax(
+                "unquote-splicing used outside of a list".to_string(),
+            )),
+            _ => Ok(wrap("unquote-splicing", quasiquote(x, depth - 1, env, gensyms)?)),
+        };
+    }
+    let list = match form {
+        FelispExp::List(list) => list,
+        FelispExp::Symbol(name) if name.len() > 1 && name.ends_with('#') => {
+            let prefix = &name[..name.len() - 1];
+            let sym = gensyms
+                .entry(name.clone())
+                .or_insert_with(|| gensym(prefix));
+            return Ok(FelispExp::Symbol(sym.clone()));
+        }
+        _ => return Ok(form.clone()),
+    };
+    let mut res = vec![];
+    for item in list {
+        match prefixed_form("unquote-splicing", item) {
+            Some(x) if depth == 1 => match eval(x, env)? {
+                FelispExp::List(spliced) => res.extend(spliced),
+                other => {
+                    return Err(FelispErr::type_error("a list to splice", &other))
+                }
+            },
+            _ => res.push(quasiquote(item, depth, env, gensyms)?),
+        }
+    }
+    Ok(FelispExp::List(res))
+}
+
+// (throw value) raises a user error carrying any value
+pub fn eval_throw_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    match arg_forms {
+        [form] => Err(FelispErr::new(FelispErrKind::User(eval(form, env)?))),
+        _ => Err(FelispErr::arity("throw", "1 argument", arg_forms.len())),
+    }
+}
+
+// Does (catch tag ...) apply to `err`. `:error` catches everything, the
+// kind names catch their kind, and a thrown keyword, or a thrown map with
+// that keyword as its `:kind`, also catches by itself.
+fn catches(tag: &str, err: &FelispErr) -> bool {
+    // Leaving the program is not an error a script can recover from
+    if let FelispErrKind::Exit(_) | FelispErrKind::Interrupted = err.kind {
+        return false;
+    }
+    if tag == "error" || tag == err.kind.name() {
+        return true;
+    }
+    let thrown = match &err.kind {
+        FelispErrKind::User(FelispExp::Map(map)) => map.get("kind"),
+        FelispErrKind::User(exp) => Some(exp),
+        _ => None,
+    };
+    match thrown {
+        Some(FelispExp::Symbol(k)) => k.strip_prefix(':') == Some(tag),
+        _ => false,
+    }
+}
+
+// What a catch clause binds: a thrown value as it is, and other errors as
+// a map of their `:kind`, `:message` and `:stack`
+pub fn error_value(err: FelispErr) -> FelispExp {
+    if let FelispErrKind::User(exp) = err.kind {
+        return exp;
+    }
+    let mut map = std::collections::BTreeMap::new();
+    map.insert(
+        "kind".to_string(),
+        FelispExp::Symbol(format!(":{}", err.kind.name())),
+    );
+    map.insert("message".to_string(), FelispExp::Str(err.message()));
+    let stack = err.stack.into_iter().map(FelispExp::Str).collect();
+    map.insert("stack".to_string(), FelispExp::List(stack));
+    FelispExp::Map(map)
+}
+
+struct CatchClause<'a> {
+    tag: String,
+    name: String,
+    body: &'a [FelispExp],
+}
+
+// (try body... (catch :kind e handler...)... (finally cleanup...))
+//
+// The value is that of the body, or of the first clause catching its
+// error. The finally forms always run afterwards, for their effects only.
+pub fn eval_try_args(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    let clause_of = |form: &FelispExp| match form {
+        FelispExp::List(list) => match list.first() {
+            Some(FelispExp::Symbol(s)) if s == "catch" || s == "finally" => Some(s.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    let body_len = arg_forms
+        .iter()
+        .position(|form| clause_of(form).is_some())
+        .unwrap_or(arg_forms.len());
+    let mut catch_clauses = vec![];
+    let mut finally_forms: Option<&[FelispExp]> = None;
+    for form in &arg_forms[body_len..] {
+        let list = match (clause_of(form), form) {
+            (Some(_), FelispExp::List(list)) => list,
+            _ => {
+                return Err(FelispErr::syntax(format!(
+                    "expected only catch and finally clauses after the try body, got {}",
+                    form
+                )))
+            }
+        };
+        if finally_forms.is_some() {
+            return Err(FelispErr::syntax("finally must be the last try clause"));
+        }
+        match &list[..] {
+            [FelispExp::Symbol(s), rest @ ..] if s == "finally" => finally_forms = Some(rest),
+            [_, FelispExp::Symbol(tag), FelispExp::Symbol(name), body @ ..]
+                if tag.len() > 1 && tag.starts_with(':') =>
+            {
+                catch_clauses.push(CatchClause {
+                    tag: tag[1..].to_string(),
+                    name: name.clone(),
+                    body,
+                })
+            }
+            _ => {
+                return Err(FelispErr::syntax(format!(
+                    "expected (catch :kind name body...), got {}",
+                    form
+                )))
+            }
+        }
+    }
+    let mut res = eval_body(&arg_forms[..body_len], env);
+    if let Err(err) = res {
+        res = match catch_clauses.iter().find(|c| catches(&c.tag, &err)) {
+            Some(clause) => {
+                let frame = new_frame(env);
+                frame
+                    .borrow_mut()
+                    .data
+                    .insert(clause.name.clone(), error_value(err));
+                eval_body(clause.body, &frame)
+            }
+            None => Err(err),
+        };
+    }
+    if let Some(forms) = finally_forms {
+        eval_body(forms, env)?;
+    }
+    res
+}
+
+// The value of the last of `forms`, nil if there are none
+pub fn eval_body(forms: &[FelispExp], env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    let mut res = FelispExp::Nil;
+    for form in forms {
+        res = eval(form, env)?;
+    }
+    Ok(res)
+}
+
+// Evaluate all but the last form for their effects, the last one is left
+// in tail position. No forms at all evaluate to nil.
+pub fn eval_do_args<'a>(
+    arg_forms: &'a [FelispExp],
+    env: &FelispEnvRef,
+) -> Result<FelispTail<'a>, FelispErr> {
+    let (last_form, init_forms) = match arg_forms.split_last() {
+        Some(forms) => forms,
+        None => return Ok(FelispTail::Value(FelispExp::Nil)),
+    };
+    for form in init_forms {
+        eval(form, env)?;
+    }
+    Ok(FelispTail::Eval(last_form, env.clone()))
+}
+
+// Check a `((name value-form) ...)` binding list, returning each pair
+pub fn parse_bindings(form: &FelispExp) -> Result<Vec<(String, &FelispExp)>, FelispErr> {
+    let bindings = match form {
+        FelispExp::List(bindings) => Ok(bindings),
+        _ => Err(FelispErr::syntax(format!(
+            "expected a list of bindings, got {}",
+            form
+        ))),
+    }?;
+    bindings
+        .iter()
+        .map(|binding| match binding {
+            FelispExp::List(pair) => match &pair[..] {
+                [FelispExp::Symbol(name), value_form] => Ok((name.clone(), value_form)),
+                [other, _] => Err(FelispErr::syntax(format!(
+                    "expected a symbol to bind, got {}",
+                    other
+                ))),
+                _ => Err(FelispErr::syntax(format!(
+                    "expected a binding of a name and a form, got {}",
+                    binding
+                ))),
+            },
+            _ => Err(FelispErr::syntax(format!(
+                "expected a binding of a name and a form, got {}",
+                binding
+            ))),
+        })
+        .collect()
+}
+
+pub fn new_frame(outer: &FelispEnvRef) -> FelispEnvRef {
+    Rc::new(RefCell::new(FelispEnv {
+        data: HashMap::new(),
+        outer: Some(outer.clone()),
+    }))
+}
+
+// `let` evaluates every value in the enclosing env, `let*` binds one name
+// at a time so each value sees the names before it, and `letrec` binds
+// into the frame its values are evaluated in, so lambdas among them can
+// refer to each other
+pub fn eval_let_args<'a>(
+    kind: &str,
+    arg_forms: &'a [FelispExp],
+    env: &FelispEnvRef,
+) -> Result<FelispTail<'a>, FelispErr> {
+    let bindings_form = arg_forms
+        .first()
+        .ok_or(FelispErr::syntax("expected bindings form".to_string()))?;
+    let bindings = parse_bindings(bindings_form)?;
+    if kind != "let*" {
+        for (i, (name, _)) in bindings.iter().enumerate() {
+            if bindings[..i].iter().any(|(other, _)| other == name) {
+                return Err(FelispErr::syntax(format!(
+                    "{} binds k='{}' more than once",
+                    kind, name
+                )));
+            }
+        }
+    }
+    let body_forms = &arg_forms[1..];
+    let mut frame = new_frame(env);
+    for (name, value_form) in bindings {
+        match kind {
+            "let" => {
+                let value = eval(value_form, env)?;
+                frame.borrow_mut().data.insert(name, value);
+            }
+            "let*" => {
+                let value = eval(value_form, &frame)?;
+                frame.borrow_mut().data.insert(name, value);
+                frame = new_frame(&frame);
+            }
+            _ => {
+                let value = eval(value_form, &frame)?;
+                frame.borrow_mut().data.insert(name, value);
+            }
+        }
+    }
+    eval_do_args(body_forms, &frame)
+}
+
+// The macro `name` refers to, if the nearest binding of it is a macro
+pub fn lookup_macro(name: &str, env: &FelispEnvRef) -> Option<FelispLambda> {
+    let env = env.borrow();
+    match env.data.get(name) {
+        Some(FelispExp::Macro(lambda)) => Some(lambda.clone()),
+        Some(_) => None,
+        None => match &env.outer {
+            Some(outer_env) => lookup_macro(name, outer_env),
+            None => None,
+        },
+    }
+}
+
+// Expand `form` once if it is a call to a macro, None if it is not
+pub fn macroexpand_1(form: &FelispExp, env: &FelispEnvRef) -> Result<Option<FelispExp>, FelispErr> {
+    let list = match form {
+        FelispExp::List(list) => list,
+        _ => return Ok(None),
+    };
+    let lambda = match list.first() {
+        Some(FelispExp::Symbol(name)) => match lookup_macro(name, env) {
+            Some(lambda) => lambda,
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let macro_env = bind_params(&lambda, list[1..].to_vec())?;
+    eval(&lambda.body_exp, &macro_env).map(Some)
+}
+
+// (macroexpand-1 form) and (macroexpand form) evaluate their argument and
+// expand it once, or for as long as it is a macro call
+pub fn eval_macroexpand_args(
+    kind: &str,
     arg_forms: &[FelispExp],
-    env: &mut FelispEnv,
-) -> Option<Result<FelispExp, FelispErr>> {
+    env: &FelispEnvRef,
+) -> Result<FelispExp, FelispErr> {
+    let mut form = match arg_forms {
+        [form] => eval(form, env)?,
+        _ => {
+            return Err(FelispErr::syntax(format!(
+                "{} expects one form, got {}",
+                kind,
+                arg_forms.len()
+            )))
+        }
+    };
+    while let Some(expanded) = macroexpand_1(&form, env)? {
+        form = expanded;
+        if kind == "macroexpand-1" {
+            break;
+        }
+    }
+    Ok(form)
+}
+
+// The names `eval_built_in_form` handles, which `defn` and `defmacro`
+// refuse to redefine
+pub const SPECIAL_FORMS: &[&str] = &[
+    "if", "cond", "when", "unless", "and", "or", "do", "begin", "let", "let*", "letrec",
+    "quote", "quasiquote", "unquote", "unquote-splicing", "try", "throw", "catch", "finally",
+    "defn", "defmacro", "macroexpand", "macroexpand-1", "set!", "fn", "select", "insert", "exit",
+];
+
+pub fn eval_built_in_form<'a>(
+    exp: &FelispExp,
+    arg_forms: &'a [FelispExp],
+    env: &FelispEnvRef,
+) -> Option<Result<FelispTail<'a>, FelispErr>> {
+    let value = FelispTail::Value;
     match exp {
         FelispExp::Symbol(s) => match s.as_ref() {
             "if" => Some(eval_if_args(arg_forms, env)),
-            "defn" => Some(eval_defn_args(arg_forms, env)),
-            "fn" => Some(eval_lambda_args(arg_forms)),
-            "select" => Some(eval_select_args(arg_forms, env)),
-            "insert" => Some(eval_insert_args(arg_forms, env)),
-            "exit" => Some(eval_exit_args(arg_forms, env)),
+            "cond" => Some(eval_cond_args(arg_forms, env)),
+            "when" | "unless" => Some(eval_when_args(s, arg_forms, env)),
+            "and" | "or" => Some(eval_and_or_args(s, arg_forms, env)),
+            "do" | "begin" => Some(eval_do_args(arg_forms, env)),
+            "let" | "let*" | "letrec" => Some(eval_let_args(s, arg_forms, env)),
+            "quote" => Some(eval_quote_args(arg_forms).map(value)),
+            "quasiquote" => Some(eval_quasiquote_args(arg_forms, env).map(value)),
+            "unquote" | "unquote-splicing" => Some(Err(FelispErr::syntax(format!(
+                "{} used outside of quasiquote",
+                s
+            )))),
+            "try" => Some(eval_try_args(arg_forms, env).map(value)),
+            "throw" => Some(eval_throw_args(arg_forms, env).map(value)),
+            "catch" | "finally" => Some(Err(FelispErr::syntax(format!(
+                "{} used outside of try",
+                s
+            )))),
+            "defn" => Some(eval_defn_args(arg_forms, env).map(value)),
+            "defmacro" => Some(eval_defmacro_args(arg_forms, env).map(value)),
+            "macroexpand" | "macroexpand-1" => {
+                Some(eval_macroexpand_args(s, arg_forms, env).map(value))
+            }
+            "set!" => Some(eval_set_args(arg_forms, env).map(value)),
+            "fn" => Some(eval_lambda_args(arg_forms, env).map(value)),
+            "select" => Some(eval_select_args(arg_forms, env).map(value)),
+            "insert" => Some(eval_insert_args(arg_forms, env).map(value)),
+            "exit" => Some(eval_exit_args(arg_forms, env).map(value)),
             _ => None,
         },
         _ => None,
     }
 }
 
-pub fn env_get(k: &str, env: &FelispEnv) -> Option<FelispExp> {
+pub fn env_get(k: &str, env: &FelispEnvRef) -> Option<FelispExp> {
+    let env = env.borrow();
     match env.data.get(k) {
         Some(exp) => Some(exp.clone()),
         None => match &env.outer {
-            Some(outer_env) => env_get(k, &outer_env),
+            Some(outer_env) => env_get(k, outer_env),
             None => None,
         },
     }
 }
 
-pub fn eval_forms(arg_forms: &[FelispExp], env: &mut FelispEnv) -> Result<Vec<FelispExp>, FelispErr> {
-    arg_forms.iter().map(|x| eval(x, env)).collect()
+// Rebind `k` in the nearest frame that defines it, returns false when no
+// frame does
+pub fn env_set(k: &str, v: FelispExp, env: &FelispEnvRef) -> bool {
+    let mut frame = env.borrow_mut();
+    match frame.data.get_mut(k) {
+        Some(exp) => {
+            *exp = v;
+            true
+        }
+        None => match &frame.outer {
+            Some(outer_env) => env_set(k, v, outer_env),
+            None => false,
+        },
+    }
+}
+
+// Every symbol bound in `env` or a frame around it, plus the names of the
+// tables they hold, sorted and without duplicates
+pub fn bound_names(env: &FelispEnvRef) -> Vec<String> {
+    let mut names = vec![];
+    let mut frame = Some(env.clone());
+    while let Some(env) = frame {
+        let env = env.borrow();
+        for (k, v) in env.data.iter() {
+            names.push(k.clone());
+            if let FelispExp::Table(t) = v {
+                names.push(t.borrow().name.clone());
+            }
+        }
+        frame = env.outer.clone();
+    }
+    names.sort();
+    names.dedup();
+    names
 }
 
+pub fn eval_forms(arg_forms: &[FelispExp], env: &FelispEnvRef) -> Result<Vec<FelispExp>, FelispErr> {
+    arg_forms.iter().map(|x| eval(x, env)).collect()
+}
 
-pub fn env_for_lambda<'a>(
-    params: Rc<FelispExp>,
+// Arguments are evaluated in the caller's env, but the new frame hangs off
+// the env the lambda was created in, which is what makes scoping lexical
+pub fn env_for_lambda(
+    lambda: &FelispLambda,
     arg_forms: &[FelispExp],
-    outer_env: &'a mut FelispEnv,
-) -> Result<FelispEnv<'a>, FelispErr> {
-    let ks = parse_list_of_symbol_strings(params)?;
-    if ks.len() != arg_forms.len() {
-        return Err(FelispErr::Reason(format!(
-            "expected {} arguments, got {}",
-            ks.len(),
-            arg_forms.len()
-        )));
-    }
-    let vs = eval_forms(arg_forms, outer_env)?;
+    caller_env: &FelispEnvRef,
+) -> Result<FelispEnvRef, FelispErr> {
+    let vs = eval_forms(arg_forms, caller_env)?;
+    bind_params(lambda, vs)
+}
+
+// Bind the lambda's params to `vs` in a new frame. A param list ending in
+// `& rest` binds `rest` to a list of whatever is left over.
+pub fn bind_params(lambda: &FelispLambda, vs: Vec<FelispExp>) -> Result<FelispEnvRef, FelispErr> {
+    let ks = parse_list_of_symbol_strings(lambda.params_exp.clone())?;
+    let (ks, rest_k) = match ks.iter().position(|k| k == "&") {
+        Some(i) if i + 2 == ks.len() => (&ks[..i], Some(ks[i + 1].clone())),
+        Some(_) => {
+            return Err(FelispErr::syntax(
+                "expected exactly one param after `&`".to_string(),
+            ))
+        }
+        None => (&ks[..], None),
+    };
+    let arity = match rest_k {
+        Some(_) => Arity::AtLeast(ks.len()),
+        None => Arity::Exactly(ks.len()),
+    };
+    arity.check(lambda.frame_name(), vs.len())?;
+    let mut vs = vs.into_iter();
     let mut data: HashMap<String, FelispExp> = HashMap::new();
-    for (k, v) in ks.iter().zip(vs.iter()) {
-        data.insert(k.clone(), v.clone());
+    for (k, v) in ks.iter().zip(vs.by_ref()) {
+        data.insert(k.clone(), v);
+    }
+    if let Some(rest_k) = rest_k {
+        data.insert(rest_k, FelispExp::List(vs.collect()));
     }
-    Ok(FelispEnv {
+    Ok(Rc::new(RefCell::new(FelispEnv {
         data,
-        outer: Some(outer_env),
-    })
+        outer: Some(lambda.env.clone()),
+    })))
+}
+
+impl Context {
+    // Call a builtin or a lambda on already evaluated arguments
+    pub fn apply(&mut self, f: &FelispExp, args: Vec<FelispExp>) -> Result<FelispExp, FelispErr> {
+        match f {
+            FelispExp::Func(f) => f.call(&args, self),
+            FelispExp::Lambda(lambda) => eval(&lambda.body_exp, &bind_params(lambda, args)?)
+                .map_err(|e| e.in_frame(lambda.frame_name())),
+            _ => Err(FelispErr::type_error("a function", f)),
+        }
+    }
+
+    // Evaluate a form as if it was written where the builtin was called
+    pub fn eval(&mut self, exp: &FelispExp) -> Result<FelispExp, FelispErr> {
+        eval(exp, &self.env)
+    }
 }
 
 // Eval function
-pub fn eval(exp: &FelispExp, env: &mut FelispEnv) -> Result<FelispExp, FelispErr> {
+//
+// The loop stands for a single Rust frame however many tail calls it goes
+// through, so on an error it adds the lambda it was last in to the stack
+pub fn eval(exp: &FelispExp, env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+    let mut frame: Option<String> = None;
+    // Forms in tail position are borrowed from `exp`, or from the last body
+    // or expansion the loop moved on to
+    let mut owner: Rc<FelispExp>;
+    let mut tail = eval_step(exp, env);
+    loop {
+        if INTERRUPTED.swap(false, Ordering::SeqCst) {
+            tail = Err(FelispErr::new(FelispErrKind::Interrupted));
+        }
+        tail = match tail {
+            Ok(FelispTail::Value(res)) => return Ok(res),
+            Ok(FelispTail::Eval(next_exp, next_env)) => eval_step(next_exp, &next_env),
+            Ok(FelispTail::Expansion(next_exp, next_env)) => {
+                owner = next_exp;
+                eval_step(&owner, &next_env)
+            }
+            Ok(FelispTail::Call(body_exp, next_env, name)) => {
+                frame = Some(name);
+                owner = body_exp;
+                eval_step(&owner, &next_env)
+            }
+            Err(e) => {
+                return Err(match frame {
+                    Some(name) => e.in_frame(&name),
+                    None => e,
+                })
+            }
+        };
+    }
+}
+
+// Evaluate `exp` up to its value, or up to the form in tail position whose
+// value will be its value
+fn eval_step<'a>(exp: &'a FelispExp, env: &FelispEnvRef) -> Result<FelispTail<'a>, FelispErr> {
     match exp {
-        FelispExp::Number(_a) => Ok(exp.clone()),
-        FelispExp::Func(_) => Err(FelispErr::Reason("unexpected form".to_string())),
-        FelispExp::Bool(_a) => Ok(exp.clone()),
+        FelispExp::Int(_a) => Ok(FelispTail::Value(exp.clone())),
+        FelispExp::BigInt(_a) => Ok(FelispTail::Value(exp.clone())),
+        FelispExp::Rational(_a) => Ok(FelispTail::Value(exp.clone())),
+        FelispExp::Float(_a) => Ok(FelispTail::Value(exp.clone())),
+        FelispExp::Func(_) => Err(FelispErr::syntax("unexpected form".to_string())),
+        FelispExp::Nil => Ok(FelispTail::Value(exp.clone())),
+        FelispExp::Bool(_a) => Ok(FelispTail::Value(exp.clone())),
+        FelispExp::Str(_a) => Ok(FelispTail::Value(exp.clone())),
+        FelispExp::Map(_a) => Ok(FelispTail::Value(exp.clone())),
         FelispExp::List(list) => {
             let first_form = list
                 .first()
-                .ok_or(FelispErr::Reason("expected a non-empty list".to_string()))?;
+                .ok_or(FelispErr::syntax("expected a non-empty list".to_string()))?;
+            if let Some(expanded) = macroexpand_1(exp, env)? {
+                return Ok(FelispTail::Expansion(Rc::new(expanded), env.clone()));
+            }
             let arg_forms = &list[1..];
             match eval_built_in_form(first_form, arg_forms, env) {
                 Some(res) => res,
@@ -199,30 +1107,758 @@ pub fn eval(exp: &FelispExp, env: &mut FelispEnv) -> Result<FelispExp, FelispErr
                     let first_eval = eval(first_form, env)?;
                     match first_eval {
                         FelispExp::Func(f) => {
-                            let args_eval = arg_forms
-                                .iter()
-                                .map(|x| eval(x, env))
-                                .collect::<Result<Vec<FelispExp>, FelispErr>>();
-                            return f(&args_eval?);
+                            let args_eval = eval_forms(arg_forms, env)?;
+                            let mut ctx = Context { env: env.clone() };
+                            Ok(FelispTail::Value(f.call(&args_eval, &mut ctx)?))
                         }
                         FelispExp::Lambda(lambda) => {
-                            let new_env = &mut env_for_lambda(lambda.params_exp, arg_forms, env)?;
-                            eval(&lambda.body_exp, new_env)
+                            let new_env = env_for_lambda(&lambda, arg_forms, env)?;
+                            let name = lambda.frame_name().to_string();
+                            Ok(FelispTail::Call(lambda.body_exp, new_env, name))
                         }
-                        _ => Err(FelispErr::Reason(
-                            "first form must be a function".to_string(),
-                        )),
+                        other => Err(FelispErr::type_error("a function", &other)),
                     }
                 }
             }
         }
-        FelispExp::Lambda(_) => Err(FelispErr::Reason("unexpected form in lambda".to_string())),
-        FelispExp::Symbol(k) => {
-            env_get(k, env).ok_or(FelispErr::Reason(format!("<< unexpected symbol k='{}'", k)))
+        FelispExp::Lambda(_) => Err(FelispErr::syntax("unexpected form in lambda".to_string())),
+        FelispExp::Macro(_) => Err(FelispErr::syntax("unexpected form in macro".to_string())),
+        // Keywords like `:id` evaluate to themselves
+        FelispExp::Symbol(k) if k.len() > 1 && k.starts_with(':') => {
+            Ok(FelispTail::Value(exp.clone()))
         }
-        FelispExp::Table(k) => env_get(&k.name[..], env).ok_or(FelispErr::Reason(format!(
-            "<< unexpected symbol k='{}'",
-            &k.name
-        ))),
+        FelispExp::Symbol(k) => env_get(k, env)
+            .map(FelispTail::Value)
+            .ok_or_else(|| FelispErr::unbound(k)),
+        FelispExp::Table(_) => Ok(FelispTail::Value(exp.clone())),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lisp_core::env::default_env;
+    use crate::lisp_core::tokenizer::tokenize;
+
+    fn run(src: &str, env: &FelispEnvRef) -> Result<FelispExp, FelispErr> {
+        let tokens = tokenize(src.to_string())?;
+        let (exp, _) = parse(&tokens)?;
+        eval(&exp, env)
+    }
+
+    fn run_all(srcs: &[&str]) -> String {
+        let env = Rc::new(RefCell::new(default_env()));
+        let mut res = String::new();
+        for src in srcs {
+            res = match run(src, &env) {
+                Ok(exp) => exp.to_string(),
+                Err(err) => format!("error: {}", err.message()),
+            };
+        }
+        res
+    }
+
+    #[test]
+    fn test_closure_keeps_defining_env() {
+        let res = run_all(&[
+            "(defn make-adder (fn (n) (fn (x) (+ x n))))",
+            "(defn add2 (make-adder 2))",
+            "(defn n 100)",
+            "(add2 3)",
+        ]);
+        assert_eq!(res, "5");
+    }
+
+    #[test]
+    fn test_closure_state_with_set() {
+        let res = run_all(&[
+            "(defn make-counter (fn (n) (fn () (set! n (+ n 1)))))",
+            "(defn c1 (make-counter 0))",
+            "(defn c2 (make-counter 10))",
+            "(c1)",
+            "(c2)",
+            "(c1)",
+        ]);
+        assert_eq!(res, "2");
+    }
+
+    #[test]
+    fn test_no_dynamic_scope() {
+        let res = run_all(&[
+            "(defn get-y (fn () y))",
+            "(defn call-with-y (fn (y) (get-y)))",
+            "(call-with-y 1)",
+        ]);
+        assert_eq!(res, "error: unbound symbol k='y'");
+    }
+
+    #[test]
+    fn test_set_unbound() {
+        assert_eq!(
+            run_all(&["(set! nope 1)"]),
+            "error: unbound symbol k='nope'"
+        );
+    }
+
+    #[test]
+    fn test_deep_tail_recursion() {
+        let res = run_all(&[
+            "(defn count-down (fn (n) (if (= n 0) 0 (count-down (- n 1)))))",
+            "(count-down 50000)",
+        ]);
+        assert_eq!(res, "0");
+    }
+
+    #[test]
+    fn test_deep_mutual_recursion() {
+        let res = run_all(&[
+            "(defn is-even (fn (n) (if (= n 0) true (is-odd (- n 1)))))",
+            "(defn is-odd (fn (n) (if (= n 0) false (is-even (- n 1)))))",
+            "(is-even 50001)",
+        ]);
+        assert_eq!(res, "false");
+    }
+
+    #[test]
+    fn test_do_and_multi_form_bodies() {
+        assert_eq!(run_all(&["(do (defn a 1) (defn b 2) (+ a b))"]), "3");
+        assert_eq!(run_all(&["(begin 1 2)"]), "2");
+        let res = run_all(&[
+            "(defn total 0)",
+            "(defn add-twice (fn (x) (set! total (+ total x)) (set! total (+ total x)) total))",
+            "(add-twice 5)",
+        ]);
+        assert_eq!(res, "10");
+        assert_eq!(run_all(&["(do)"]), "nil");
+    }
+
+    #[test]
+    fn test_let_forms() {
+        assert_eq!(run_all(&["(let ((x 1) (y 2)) (+ x y))"]), "3");
+        assert_eq!(run_all(&["(defn x 10)", "(let ((x 1) (y x)) y)"]), "10");
+        assert_eq!(run_all(&["(let* ((x 1) (y (+ x 1))) (* x y))"]), "2");
+        assert_eq!(run_all(&["(let* ((x 1) (x (+ x 1))) x)"]), "2");
+        let res = run_all(&[
+            "(letrec ((ev (fn (n) (if (= n 0) true (od (- n 1))))) \
+                      (od (fn (n) (if (= n 0) false (ev (- n 1)))))) \
+               (ev 10))",
+        ]);
+        assert_eq!(res, "true");
+        assert_eq!(run_all(&["(let () 1 2 3)"]), "3");
+        assert_eq!(run_all(&["(let ((x 1)) x)", "x"]), "error: unbound symbol k='x'");
+    }
+
+    #[test]
+    fn test_let_in_tail_position() {
+        let res = run_all(&[
+            "(defn count-down (fn (n) (let ((m (- n 1))) (if (= n 0) 0 (do (count-down m))))))",
+            "(count-down 20000)",
+        ]);
+        assert_eq!(res, "0");
+    }
+
+    #[test]
+    fn test_malformed_bindings() {
+        assert_eq!(run_all(&["(let x 1)"]), "error: expected a list of bindings, got x");
+        assert_eq!(
+            run_all(&["(let ((x)) x)"]),
+            "error: expected a binding of a name and a form, got (x)"
+        );
+        assert_eq!(
+            run_all(&["(let (x 1) x)"]),
+            "error: expected a binding of a name and a form, got x"
+        );
+        assert_eq!(
+            run_all(&["(let ((1 2)) 1)"]),
+            "error: expected a symbol to bind, got 1"
+        );
+        assert_eq!(
+            run_all(&["(let ((x 1) (x 2)) x)"]),
+            "error: let binds k='x' more than once"
+        );
+        assert_eq!(run_all(&["(fn (x x) x)"]), "error: fn binds k='x' more than once");
+        assert_eq!(run_all(&["(fn (x & x) x)"]), "error: fn binds k='x' more than once");
+        assert_eq!(run_all(&["(let ((x 1)))"]), "nil");
+    }
+
+    #[test]
+    fn test_truthiness() {
+        assert_eq!(run_all(&["(if nil 1 2)"]), "2");
+        assert_eq!(run_all(&["(if 0 1 2)"]), "1");
+        assert_eq!(run_all(&["(if \"\" 1 2)"]), "1");
+        assert_eq!(run_all(&["(if false 1)"]), "nil");
+        assert_eq!(run_all(&["(not nil)"]), "true");
+        assert_eq!(run_all(&["(not 0)"]), "false");
+        assert_eq!(
+            run_all(&["(if true 1 2 3)"]),
+            "error: if expects a test, a then form and an optional else form"
+        );
+    }
+
+    #[test]
+    fn test_cond() {
+        let sign = "(defn sign (fn (n) (cond ((< n 0) -1) ((= n 0) 0) (else 1))))";
+        assert_eq!(run_all(&[sign, "(sign -5)"]), "-1");
+        assert_eq!(run_all(&[sign, "(sign 0)"]), "0");
+        assert_eq!(run_all(&[sign, "(sign 7)"]), "1");
+        assert_eq!(run_all(&["(cond (false 1) (nil 2))"]), "nil");
+        assert_eq!(run_all(&["(cond (false 1) (42))"]), "42");
+        assert_eq!(run_all(&["(cond (true (defn a 1) (+ a 1)))"]), "2");
+        assert_eq!(
+            run_all(&["(cond (else 1) (true 2))"]),
+            "error: else must be the last cond clause"
+        );
+        assert_eq!(
+            run_all(&["(cond 1)"]),
+            "error: expected a cond clause of a test and a body, got 1"
+        );
+    }
+
+    #[test]
+    fn test_when_unless() {
+        assert_eq!(run_all(&["(when (> 2 1) 1 2)"]), "2");
+        assert_eq!(run_all(&["(when nil (undefined))"]), "nil");
+        assert_eq!(run_all(&["(unless false 3)"]), "3");
+        assert_eq!(run_all(&["(unless 0 3)"]), "nil");
+    }
+
+    #[test]
+    fn test_and_or_short_circuit() {
+        assert_eq!(run_all(&["(and)"]), "true");
+        assert_eq!(run_all(&["(or)"]), "nil");
+        assert_eq!(run_all(&["(and 1 2 3)"]), "3");
+        assert_eq!(run_all(&["(and 1 nil (undefined))"]), "nil");
+        assert_eq!(run_all(&["(or false 2 (undefined))"]), "2");
+        assert_eq!(run_all(&["(or false nil)"]), "nil");
+        let res = run_all(&[
+            "(defn all-even (fn (n) (or (= n 0) (and (= (mod n 2) 0) (all-even (- n 2))))))",
+            "(all-even 50000)",
+        ]);
+        assert_eq!(res, "true");
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(run_all(&["'(+ 1 2)"]), "(+ 1 2)");
+        assert_eq!(run_all(&["(quote undefined)"]), "undefined");
+        assert_eq!(run_all(&["''a"]), "(quote a)");
+        assert_eq!(run_all(&["(quote a b)"]), "error: quote expects one form, got 2");
+    }
+
+    #[test]
+    fn test_quasiquote() {
+        let res = run_all(&["(defn x 1)", "(defn xs '(2 3))", "`(a ,x ,@xs (b ,(+ x 1)) ,@'())"]);
+        assert_eq!(res, "(a 1 2 3 (b 2))");
+        assert_eq!(run_all(&["`x"]), "x");
+        assert_eq!(
+            run_all(&["(defn x 1)", "`(a `(b ,(c ,x)))"]),
+            "(a (quasiquote (b (unquote (c 1)))))"
+        );
+        assert_eq!(run_all(&[",x"]), "error: unquote used outside of quasiquote");
+        assert_eq!(
+            run_all(&["`,@'(1)"]),
+            "error: unquote-splicing used outside of a list"
+        );
+        assert_eq!(
+            run_all(&["`(a ,@1)"]),
+            "error: expected a list to splice, got 1"
+        );
+    }
+
+    #[test]
+    fn test_list_builtins() {
+        assert_eq!(run_all(&["(list 1 (+ 1 1) \"c\")"]), "(1 2 \"c\")");
+        assert_eq!(run_all(&["(cons 0 '(1 2))"]), "(0 1 2)");
+        assert_eq!(run_all(&["(first '(1 2))"]), "1");
+        assert_eq!(run_all(&["(first '())"]), "nil");
+        assert_eq!(run_all(&["(rest '(1 2))"]), "(2)");
+        assert_eq!(run_all(&["(count '(1 2))"]), "2");
+        assert_eq!(run_all(&["(empty? '())"]), "true");
+        assert_eq!(run_all(&["(first 1)"]), "error: expected a list, got 1");
+        assert_eq!(run_all(&["(cons 1 2)"]), "error: expected a list, got 2");
+    }
+
+    #[test]
+    fn test_insert_row_tuple() {
+        let env = Rc::new(RefCell::new(default_env()));
+        run("(insert mytable1 '(5 \"bob\" \"bob@x.com\"))", &env).ok().unwrap();
+        run("(insert mytable1 (list \"amy\" \"amy@x.com\"))", &env).ok().unwrap();
+        let rows = match env_get("mytable1", &env) {
+            Some(FelispExp::Table(t)) => t.borrow().pages[0].clone(),
+            _ => panic!("expected mytable1 to be a table"),
+        };
+        assert_eq!(rows[0].as_ref().map(|r| (r.id, &r.username[..])), Some((5, "bob")));
+        assert_eq!(rows[1].as_ref().map(|r| (r.id, &r.username[..])), Some((6, "amy")));
+        assert_eq!(
+            run_all(&["(insert mytable1 '(\"bob\"))"]),
+            "error: insert expects a table, an optional id, a username and an email"
+        );
+        assert_eq!(
+            run_all(&["(insert mytable1 5)"]),
+            "error: expected a row as a list, got 5"
+        );
+    }
+
+    #[test]
+    fn test_insert_ids() {
+        let env = Rc::new(RefCell::new(default_env()));
+        run("(insert mytable1 \"amy\" \"amy@x.com\")", &env).ok().unwrap();
+        run("(insert mytable1 2 \"bob\" \"bob@x.com\")", &env).ok().unwrap();
+        let row = run("(insert mytable1 \"cy\" \"cy@x.com\")", &env).ok().unwrap();
+        assert_eq!(row.to_string(), "{:email \"cy@x.com\", :id 3, :username \"cy\"}");
+        let res = run("(insert mytable1 2 \"dee\" \"dee@x.com\")", &env);
+        assert_eq!(res.err().unwrap().message(), "duplicate row id 2");
+        let res = run("(map (fn (r) (get r :id)) (select mytable1))", &env);
+        assert_eq!(res.ok().unwrap().to_string(), "(1 2 3)");
+    }
+
+    #[test]
+    fn test_select_rows() {
+        let env = Rc::new(RefCell::new(default_env()));
+        assert_eq!(run("(select mytable1)", &env).ok().unwrap().to_string(), "()");
+        let row = run("(insert mytable1 7 \"amy\" \"amy@x.com\")", &env).ok().unwrap();
+        assert_eq!(row.to_string(), "{:email \"amy@x.com\", :id 7, :username \"amy\"}");
+        run("(insert mytable1 \"bob\" \"bob@y.com\")", &env).ok().unwrap();
+        let res = run("(map (fn (r) (get r :username)) (select mytable1))", &env);
+        assert_eq!(res.ok().unwrap().to_string(), "(\"amy\" \"bob\")");
+        let res = run("(map (fn (r) (get r :id)) (select mytable1))", &env);
+        assert_eq!(res.ok().unwrap().to_string(), "(7 8)");
+        assert_eq!(run_all(&["(select 5)"]), "error: expected a table, got 5");
+        assert_eq!(run_all(&["(select)"]), "error: select expected at least 1 argument, got 0");
+        assert_eq!(
+            run_all(&["(insert '(1) \"a\" \"b\")"]),
+            "error: expected a table, got (1)"
+        );
+    }
+
+    #[test]
+    fn test_table_shared() {
+        let env = Rc::new(RefCell::new(default_env()));
+        run("(let ((t mytable1)) (insert t 1 \"amy\" \"amy@x.com\"))", &env).ok().unwrap();
+        let res = run("(count (select mytable1))", &env);
+        assert_eq!(res.ok().unwrap().to_string(), "1");
+        let res = run(
+            "(select mytable1 :where (fn (r) (insert mytable1 \"bob\" \"bob@x.com\")))",
+            &env,
+        );
+        assert_eq!(res.err().unwrap().message(), "mytable1 is being read");
+    }
+
+    #[test]
+    fn test_select_where() {
+        let env = Rc::new(RefCell::new(default_env()));
+        run(
+            "(map (fn (r) (insert mytable1 r)) \
+               '((\"amy\" \"amy@x.com\") (\"bob\" \"bob@y.com\") (\"cy\" \"cy@x.com\")))",
+            &env,
+        )
+        .ok()
+        .unwrap();
+        let names = |src: &str| {
+            let src = format!("(map (fn (r) (get r :username)) {})", src);
+            match run(&src, &env) {
+                Ok(res) => res.to_string(),
+                Err(e) => format!("error: {}", e.message()),
+            }
+        };
+        assert_eq!(names("(select mytable1 :where (= id 2))"), "(\"bob\")");
+        assert_eq!(
+            names("(select mytable1 :where (fn (r) (= (get r :username) \"amy\")))"),
+            "(\"amy\")"
+        );
+        assert_eq!(names("(select mytable1 :where (like email \"%@x.com\"))"), "(\"amy\" \"cy\")");
+        assert_eq!(
+            names("(let ((n 1)) (select mytable1 :where (and (> id n) (not (= username \"cy\")))))"),
+            "(\"bob\")"
+        );
+        assert_eq!(
+            names("(select mytable1 :where (or (< id 2) (fn (r) (= (get r :id) 3))))"),
+            "(\"amy\" \"cy\")"
+        );
+        assert_eq!(names("(select mytable1 :where (fn (r) (> (get r :id) 1)))"), "(\"bob\" \"cy\")");
+        assert_eq!(names("(let ((x 1)) (select mytable1 :where (= x 1)))"), "(\"amy\" \"bob\" \"cy\")");
+        assert_eq!(names("(let ((x 1)) (select mytable1 :where (= x 2)))"), "()");
+        assert_eq!(names("(select mytable1 :where (= age 2))"), "error: unbound symbol k='age'");
+        assert_eq!(names("(select mytable1 :where (= id \"2\"))"), "error: expected an integer, got \"2\"");
+        assert_eq!(names("(select mytable1 :where 5)"), "error: expected a predicate, got 5");
+        assert_eq!(names("(select mytable1 :where nil)"), "()");
+        assert_eq!(names("(select mytable1 :where false)"), "()");
+        assert_eq!(
+            names("(select mytable1 :where (like id \"1%\"))"),
+            "error: expected a string column, got id"
+        );
+        assert_eq!(
+            names("(select mytable1 :where (fn (r) (first r)))"),
+            "error: expected a list, got {:email \"amy@x.com\", :id 1, :username \"amy\"}"
+        );
+        assert_eq!(names("(select mytable1 :where)"), "error: expected a value after :where");
+        assert_eq!(names("(select mytable1 :sort 1)"), "error: unknown select clause :sort");
+    }
+
+    #[test]
+    fn test_select_clauses() {
+        let env = Rc::new(RefCell::new(default_env()));
+        run(
+            "(map (fn (r) (insert mytable1 r)) \
+               '((\"cy\" \"c@x.com\") (\"amy\" \"a@y.com\") (\"bob\" \"b@x.com\") (\"amy\" \"a2@x.com\")))",
+            &env,
+        )
+        .ok()
+        .unwrap();
+        let select = |src: &str| match run(src, &env) {
+            Ok(res) => res.to_string(),
+            Err(e) => format!("error: {}", e.message()),
+        };
+        assert_eq!(
+            select("(select mytable1 :columns (id) :order-by (username :desc))"),
+            "({:id 1} {:id 3} {:id 2} {:id 4})"
+        );
+        assert_eq!(
+            select("(select mytable1 :columns (username id) :order-by username :offset 1 :limit 2)"),
+            "({:id 4, :username \"amy\"} {:id 3, :username \"bob\"})"
+        );
+        assert_eq!(
+            select("(select mytable1 :where (like email \"%@x.com\") :columns (email) :limit (+ 1 1))"),
+            "({:email \"c@x.com\"} {:email \"b@x.com\"})"
+        );
+        assert_eq!(select("(count (select mytable1 :offset 10))"), "0");
+        assert_eq!(select("(select mytable1 :columns (age))"), "error: no column named age");
+        assert_eq!(select("(select mytable1 :columns id)"), "error: expected a list of columns, got id");
+        assert_eq!(
+            select("(select mytable1 :order-by (id :up))"),
+            "error: expected :asc or :desc, got :up"
+        );
+        assert_eq!(
+            select("(select mytable1 :order-by (id :desc 1))"),
+            "error: expected (column :asc|:desc), got (id :desc 1)"
+        );
+        assert_eq!(select("(select mytable1 :limit -1)"), "error: expected a count, got -1");
+    }
+
+    #[test]
+    fn test_rest_params() {
+        assert_eq!(run_all(&["((fn (a & more) (list a more)) 1 2 3)"]), "(1 (2 3))");
+        assert_eq!(run_all(&["((fn (& xs) xs))"]), "()");
+        assert_eq!(
+            run_all(&["((fn (a b & more) a) 1)"]),
+            "error: fn expected at least 2 arguments, got 1"
+        );
+        assert_eq!(
+            run_all(&["((fn (a & b c) a) 1)"]),
+            "error: expected exactly one param after `&`"
+        );
+    }
+
+    #[test]
+    fn test_defmacro() {
+        let my_unless = "(defmacro my-unless (test & body) `(if ,test nil (do ,@body)))";
+        assert_eq!(run_all(&[my_unless, "(my-unless false 1 2)"]), "2");
+        assert_eq!(run_all(&[my_unless, "(my-unless true (undefined))"]), "nil");
+        assert_eq!(
+            run_all(&["(defmacro if (a b c) c)"]),
+            "error: cannot redefine special form if"
+        );
+        assert_eq!(run_all(&["(try (defmacro if (a b c) c) (catch :error e 0))", "(if true 1 2)"]), "1");
+        assert_eq!(
+            run_all(&["(defn let (fn (x) x))"]),
+            "error: cannot redefine special form let"
+        );
+        assert_eq!(
+            run_all(&[my_unless, "(macroexpand-1 '(my-unless x y))"]),
+            "(if x nil (do y))"
+        );
+        assert_eq!(run_all(&[my_unless, "(macroexpand '(+ 1 2))"]), "(+ 1 2)");
+        let res = run_all(&[
+            "(defmacro add-user (name email) `(insert mytable1 ,name ,email))",
+            "(add-user \"amy\" \"amy@x.com\")",
+        ]);
+        assert_eq!(res, "{:email \"amy@x.com\", :id 1, :username \"amy\"}");
+    }
+
+    #[test]
+    fn test_macroexpand_repeats() {
+        let res = run_all(&[
+            "(defmacro inner (x) `(+ ,x 1))",
+            "(defmacro outer (x) `(inner ,x))",
+            "(list (macroexpand-1 '(outer 2)) (macroexpand '(outer 2)) (outer 2))",
+        ]);
+        assert_eq!(res, "((inner 2) (+ 2 1) 3)");
+    }
+
+    #[test]
+    fn test_macro_expands_in_tail_position() {
+        let res = run_all(&[
+            "(defmacro my-if (c a b) `(cond (,c ,a) (else ,b)))",
+            "(defn count-down (fn (n) (my-if (= n 0) 0 (count-down (- n 1)))))",
+            "(count-down 20000)",
+        ]);
+        assert_eq!(res, "0");
+    }
+
+    #[test]
+    fn test_gensym_avoids_capture() {
+        let res = run_all(&[
+            "(defmacro my-or (a b) `(let ((v# ,a)) (if v# v# ,b)))",
+            "(let ((v 7)) (my-or false v))",
+        ]);
+        assert_eq!(res, "7");
+        let res = run_all(&[
+            "(defmacro bad-or (a b) `(let ((v ,a)) (if v v ,b)))",
+            "(let ((v 7)) (bad-or false v))",
+        ]);
+        assert_eq!(res, "false");
+        let res = run_all(&["(defmacro two (x) `(list 'a# 'a# 'b#))", "(two 1)"]);
+        let syms: Vec<String> = res[1..res.len() - 1].split(' ').map(String::from).collect();
+        assert_eq!(syms[0], syms[1]);
+        assert_ne!(syms[0], syms[2]);
+        assert!(syms[0].starts_with("a__"));
+        assert_ne!(run_all(&["(gensym)"]), run_all(&["(gensym)"]));
+        assert!(run_all(&["(gensym \"tmp\")"]).starts_with("tmp__"));
+    }
+
+    #[test]
+    fn test_higher_order_builtins() {
+        assert_eq!(run_all(&["(map (fn (x) (* x x)) '(1 2 3))"]), "(1 4 9)");
+        assert_eq!(run_all(&["(map + '(1 2 3) '(10 20))"]), "(11 22)");
+        assert_eq!(run_all(&["(filter (fn (x) (> x 1)) '(1 2 3))"]), "(2 3)");
+        assert_eq!(run_all(&["(reduce + '(1 2 3))"]), "6");
+        assert_eq!(run_all(&["(reduce cons '() '(1 2))"]), "error: expected a list, got 1");
+        assert_eq!(run_all(&["(reduce (fn (acc x) (cons x acc)) '() '(1 2))"]), "(2 1)");
+        assert_eq!(
+            run_all(&["(reduce + '())"]),
+            "error: expected a non-empty list or an initial value, got ()"
+        );
+        assert_eq!(run_all(&["(apply + 1 2 '(3 4))"]), "10");
+        assert_eq!(run_all(&["(apply (fn (a & b) b) '(1 2 3))"]), "(2 3)");
+        assert_eq!(run_all(&["(map 1 '(1))"]), "error: expected a function, got 1");
+        assert_eq!(run_all(&["(let ((x 2)) (eval `(* x ,x 3)))"]), "12");
+    }
+
+    #[test]
+    fn test_stateful_builtin() {
+        let env = Rc::new(RefCell::new(default_env()));
+        let calls = Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        let func = FelispFunc::new("next-id", Arity::Exactly(0), move |_, ctx| {
+            counter.set(counter.get() + 1);
+            let base = env_get("base", &ctx.env).unwrap_or(FelispExp::Int(0));
+            match base {
+                FelispExp::Int(n) => Ok(FelispExp::Int(n + counter.get())),
+                _ => Ok(FelispExp::Nil),
+            }
+        });
+        env.borrow_mut().data.insert("next-id".to_string(), FelispExp::Func(func));
+        run("(next-id)", &env).ok().unwrap();
+        let res = run("(let ((base 100)) (next-id))", &env).ok().unwrap();
+        assert_eq!(res.to_string(), "102");
+        assert_eq!(calls.get(), 2);
+        assert_eq!(
+            run("(next-id 1)", &env).unwrap_err().message(),
+            "next-id expected 0 arguments, got 1"
+        );
+    }
+
+    #[test]
+    fn test_special_forms() {
+        let env = Rc::new(RefCell::new(default_env()));
+        for name in SPECIAL_FORMS {
+            let head = FelispExp::Symbol(name.to_string());
+            assert!(eval_built_in_form(&head, &[], &env).is_some(), "{}", name);
+        }
+        let head = FelispExp::Symbol("map".to_string());
+        assert!(eval_built_in_form(&head, &[], &env).is_none());
+    }
+
+    #[test]
+    fn test_debug_shows_variant() {
+        let env = Rc::new(RefCell::new(default_env()));
+        let res = run("(list 1 \"1\" 'a 1.5 nil (hash-map :k 2) first)", &env).unwrap();
+        assert_eq!(
+            format!("{:?}", res),
+            "List([Int(1), Str(\"1\"), Symbol(\"a\"), Float(1.5), Nil, \
+             Map({\"k\": Int(2)}), Func(first)])"
+        );
+        run("(defn sq (fn (x) (* x x)))", &env).unwrap();
+        assert_eq!(format!("{:?}", run("sq", &env).unwrap()), "Lambda(sq)");
+    }
+
+    #[test]
+    fn test_bound_names() {
+        let env = Rc::new(RefCell::new(default_env()));
+        run("(defn zebra 1)", &env).ok().unwrap();
+        let mut data = HashMap::new();
+        data.insert("zeta".to_string(), FelispExp::Nil);
+        data.insert("zebra".to_string(), FelispExp::Nil);
+        let inner = Rc::new(RefCell::new(FelispEnv { data, outer: Some(env) }));
+        let names = bound_names(&inner);
+        let z: Vec<&String> = names.iter().filter(|n| n.starts_with('z')).collect();
+        assert_eq!(z, vec!["zebra", "zeta"]);
+        assert!(names.contains(&"mytable1".to_string()));
+        assert!(names.contains(&"map".to_string()));
+    }
+
+    #[test]
+    fn test_keywords_and_maps() {
+        assert_eq!(run_all(&[":id"]), ":id");
+        let m = "(defn m (hash-map :id 1 \"name\" \"bob\"))";
+        assert_eq!(run_all(&[m, "m"]), "{:id 1, :name \"bob\"}");
+        assert_eq!(run_all(&[m, "(get m :name)"]), "\"bob\"");
+        assert_eq!(run_all(&[m, "(get m :email)"]), "nil");
+        assert_eq!(run_all(&[m, "(get m :email \"none\")"]), "\"none\"");
+        assert_eq!(run_all(&[m, "(assoc m :id 2 :email \"b@x\")"]), "{:email \"b@x\", :id 2, :name \"bob\"}");
+        assert_eq!(run_all(&[m, "(assoc m :id 2)", "m"]), "{:id 1, :name \"bob\"}");
+        assert_eq!(run_all(&[m, "(dissoc m :id :nope)"]), "{:name \"bob\"}");
+        assert_eq!(run_all(&[m, "(list (contains? m :id) (keys m) (vals m))"]), "(true (:id :name) (1 \"bob\"))");
+        assert_eq!(
+            run_all(&["(hash-map :a)"]),
+            "error: hash-map expected keys and values in pairs, got 1"
+        );
+        assert_eq!(
+            run_all(&["(hash-map 1 2)"]),
+            "error: expected a keyword or string key, got 1"
+        );
+        assert_eq!(run_all(&["(get '(1) :a)"]), "error: expected a map, got (1)");
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let env = Rc::new(RefCell::new(default_env()));
+        let kind = |src: &str| run(src, &env).unwrap_err().kind;
+        assert!(matches!(kind("(1 2"), FelispErrKind::Read(_)));
+        assert!(matches!(kind("(let x 1)"), FelispErrKind::Syntax(_)));
+        assert!(matches!(kind("nope"), FelispErrKind::UnboundSymbol(k) if k == "nope"));
+        assert!(matches!(
+            kind("(+ 1 \"a\")"),
+            FelispErrKind::Type { expected, got } if expected == "a number" && got == "\"a\""
+        ));
+        assert!(matches!(
+            kind("((fn (x) x))"),
+            FelispErrKind::Arity { name, got: 0, .. } if name == "fn"
+        ));
+        assert!(matches!(kind("(/ 1 0)"), FelispErrKind::Arithmetic(_)));
+        assert!(matches!(
+            kind("(insert mytable1 9999999999 \"a\" \"b\")"),
+            FelispErrKind::Db(_)
+        ));
+    }
+
+    #[test]
+    fn test_error_call_stack() {
+        let env = Rc::new(RefCell::new(default_env()));
+        run("(defn g (fn (x) (first x)))", &env).unwrap();
+        run("(defn f (fn (x) (+ 1 (g x))))", &env).unwrap();
+        run("(defn h (fn (x) (g x)))", &env).unwrap();
+        let err = run("(f 1)", &env).unwrap_err();
+        assert_eq!(err.stack, vec!["first", "g", "f"]);
+        assert_eq!(err.to_string(), "expected a list, got 1\n  in first\n  in g\n  in f");
+        // h calls g in tail position, so g's frame replaces it
+        assert_eq!(run("(h 1)", &env).unwrap_err().stack, vec!["first", "g"]);
+        let err = run("(map (fn (x) (g x)) '(1))", &env).unwrap_err();
+        assert_eq!(err.stack, vec!["first", "g", "fn", "map"]);
+        assert!(run("(first 1)", &env).unwrap_err().stack == vec!["first"]);
+        assert!(run("nope", &env).unwrap_err().stack.is_empty());
+        let err: Box<dyn std::error::Error> = Box::new(run("(g 1)", &env).unwrap_err());
+        assert_eq!(err.to_string(), "expected a list, got 1\n  in first\n  in g");
+    }
+
+    #[test]
+    fn test_try_catch() {
+        assert_eq!(run_all(&["(try (+ 1 2) (catch :error e 0))"]), "3");
+        assert_eq!(run_all(&["(try (/ 1 0) (catch :error e 0))"]), "0");
+        assert_eq!(
+            run_all(&["(try (/ 1 0) (catch :type-error e 1) (catch :arithmetic-error e (get e :message)))"]),
+            "\"/: divide by zero\""
+        );
+        assert_eq!(
+            run_all(&["(try (first 1) (catch :type-error e e))"]),
+            "{:kind :type-error, :message \"expected a list, got 1\", :stack (\"first\")}"
+        );
+        assert_eq!(run_all(&["(try (/ 1 0) (catch :type-error e 1))"]), "error: /: divide by zero");
+        assert_eq!(run_all(&["(try)"]), "nil");
+        assert_eq!(run_all(&["(try (undefined) (catch :unbound-symbol e 1) (catch :error e 2))"]), "1");
+    }
+
+    #[test]
+    fn test_throw() {
+        assert_eq!(run_all(&["(try (throw '(1 2)) (catch :user-error e (rest e)))"]), "(2)");
+        assert_eq!(run_all(&["(throw \"bad row\")"]), "error: bad row");
+        assert_eq!(run_all(&["(try (throw :bad-row) (catch :bad-row e e))"]), ":bad-row");
+        assert_eq!(
+            run_all(&["(try (throw (hash-map :kind :bad-row :id 3)) (catch :other e 1) (catch :bad-row e (get e :id)))"]),
+            "3"
+        );
+        assert_eq!(run_all(&["(try (throw 'bad-row) (catch :ad-row e 1) (catch :error e 2))"]), "2");
+        let res = run_all(&[
+            "(defn check (fn (x) (if (> x 1) (throw (list :too-big x)) x)))",
+            "(try (check 5) (catch :error e (first (rest e))))",
+        ]);
+        assert_eq!(res, "5");
+    }
+
+    #[test]
+    fn test_finally() {
+        let res = run_all(&[
+            "(defn log '())",
+            "(defn r (try (/ 1 0) (catch :error e :caught) (finally (set! log (cons :cleanup log)))))",
+            "(list r log)",
+        ]);
+        assert_eq!(res, "(:caught (:cleanup))");
+        let res = run_all(&[
+            "(defn log '())",
+            "(try (try (throw :x) (finally (set! log (cons :inner log)))) (catch :x e :outer))",
+            "(list log)",
+        ]);
+        assert_eq!(res, "((:inner))");
+        assert_eq!(run_all(&["(try 1 (finally 2))"]), "1");
+        assert_eq!(run_all(&["(try 1 (finally (throw \"oops\")))"]), "error: oops");
+    }
+
+    #[test]
+    fn test_exit() {
+        let env = Rc::new(RefCell::new(default_env()));
+        let code = |src: &str| match run(src, &env).unwrap_err().kind {
+            FelispErrKind::Exit(code) => Some(code),
+            _ => None,
+        };
+        assert_eq!(code("(exit)"), Some(0));
+        assert_eq!(code("(exit (+ 1 2))"), Some(3));
+        assert_eq!(code("(try (exit 2) (catch :error e 0))"), Some(2));
+        assert_eq!(code("(try (exit 4) (catch :exit e 0))"), Some(4));
+        assert_eq!(run_all(&["(exit \"no\")"]), "error: expected an exit status, got \"no\"");
+        assert_eq!(run_all(&["(exit 256)"]), "error: expected an exit status, got 256");
+        assert_eq!(run_all(&["(exit -1)"]), "error: expected an exit status, got -1");
+        assert_eq!(run_all(&["(exit 1 2)"]), "error: exit expected at most 1 argument, got 2");
+    }
+
+    #[test]
+    fn test_malformed_try() {
+        assert_eq!(
+            run_all(&["(try 1 (catch :error e 2) 3)"]),
+            "error: expected only catch and finally clauses after the try body, got 3"
+        );
+        assert_eq!(
+            run_all(&["(try 1 (finally 2) (catch :error e 3))"]),
+            "error: finally must be the last try clause"
+        );
+        assert_eq!(
+            run_all(&["(try 1 (catch error e 3))"]),
+            "error: expected (catch :kind name body...), got (catch error e 3)"
+        );
+        assert_eq!(run_all(&["(catch :error e 1)"]), "error: catch used outside of try");
+        assert_eq!(run_all(&["(throw)"]), "error: throw expected 1 argument, got 0");
+    }
+
+    #[test]
+    fn test_skip_bad_rows() {
+        let res = run_all(&[
+            "(defn import (fn (rows) \
+               (map (fn (row) (try (insert mytable1 row) :ok (catch :error e (get e :message)))) rows)))",
+            "(import '((1 \"amy\" \"amy@x.com\") (2 \"bob\") (3 \"cy\" \"cy@x.com\")))",
+        ]);
+        assert_eq!(
+            res,
+            "(:ok \"expected a string, got 2\" :ok)"
+        );
     }
 }
@@ -2,3 +2,4 @@ pub mod parser;
 pub mod tokenizer;
 pub mod env;
 pub mod eval;
+pub mod numeric;
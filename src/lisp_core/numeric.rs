@@ -0,0 +1,470 @@
+// The numeric tower: integers, big integers, exact rationals and floats.
+// An operation on mixed arguments first promotes both to the wider kind,
+// so `(+ 1 2)` stays an integer, `(/ 1 3)` is the exact rational 1/3 and
+// `(+ 1 2.0)` is a float. Integer operations that overflow i64 carry on in
+// big integers, and exact results are brought back down to the narrowest
+// kind that holds them, so `(* 1/3 3)` is the integer 1.
+
+use std::cmp::Ordering;
+
+use num_bigint::{BigInt, BigUint};
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
+use crate::lib::data::*;
+
+#[derive(Debug, Clone)]
+pub enum Num {
+    Int(i64),
+    Big(BigInt),
+    Ratio(BigRational),
+    Float(f64),
+}
+
+impl Num {
+    pub fn from_exp(exp: &FelispExp) -> Result<Num, FelispErr> {
+        match exp {
+            FelispExp::Int(i) => Ok(Num::Int(*i)),
+            FelispExp::BigInt(i) => Ok(Num::Big(i.clone())),
+            FelispExp::Rational(r) => Ok(Num::Ratio(r.clone())),
+            FelispExp::Float(f) => Ok(Num::Float(*f)),
+            _ => Err(FelispErr::type_error("a number", exp)),
+        }
+    }
+
+    pub fn into_exp(self) -> FelispExp {
+        match self {
+            Num::Int(i) => FelispExp::Int(i),
+            Num::Big(i) => FelispExp::BigInt(i),
+            Num::Ratio(r) => FelispExp::Rational(r),
+            Num::Float(f) => FelispExp::Float(f),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Num::Int(i) => *i as f64,
+            Num::Big(i) => i.to_f64().unwrap_or(f64::NAN),
+            Num::Ratio(r) => r.to_f64().unwrap_or(f64::NAN),
+            Num::Float(f) => *f,
+        }
+    }
+
+    // The exact value, None for floats
+    pub fn to_ratio(&self) -> Option<BigRational> {
+        match self {
+            Num::Int(i) => Some(BigRational::from_integer(BigInt::from(*i))),
+            Num::Big(i) => Some(BigRational::from_integer(i.clone())),
+            Num::Ratio(r) => Some(r.clone()),
+            Num::Float(_) => None,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Num::Int(i) => *i == 0,
+            Num::Big(i) => i.is_zero(),
+            Num::Ratio(r) => r.is_zero(),
+            Num::Float(f) => *f == 0.0,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Num::Int(i) => *i < 0,
+            Num::Big(i) => i.is_negative(),
+            Num::Ratio(r) => r.is_negative(),
+            Num::Float(f) => *f < 0.0,
+        }
+    }
+}
+
+impl PartialEq for Num {
+    fn eq(&self, other: &Num) -> bool {
+        compare(self, other) == Ordering::Equal
+    }
+}
+
+impl PartialOrd for Num {
+    fn partial_cmp(&self, other: &Num) -> Option<Ordering> {
+        Some(compare(self, other))
+    }
+}
+
+fn from_big(i: BigInt) -> Num {
+    match i.to_i64() {
+        Some(small) => Num::Int(small),
+        None => Num::Big(i),
+    }
+}
+
+fn from_ratio(r: BigRational) -> Num {
+    if r.is_integer() {
+        from_big(r.to_integer())
+    } else {
+        Num::Ratio(r)
+    }
+}
+
+fn overflow(name: &str) -> FelispErr {
+    FelispErr::arithmetic(format!("{}: integer overflow", name))
+}
+
+// Floats never hold NaN or an infinity, those are reported as errors
+pub fn float_result(name: &str, x: f64) -> Result<Num, FelispErr> {
+    if x.is_nan() {
+        Err(FelispErr::arithmetic(format!("{}: argument out of domain", name)))
+    } else if x.is_infinite() {
+        Err(FelispErr::arithmetic(format!("{}: result out of range", name)))
+    } else {
+        Ok(Num::Float(x))
+    }
+}
+
+fn ensure_non_zero_divisor(name: &str, b: &Num) -> Result<(), FelispErr> {
+    if b.is_zero() {
+        Err(FelispErr::arithmetic(format!("{}: divide by zero", name)))
+    } else {
+        Ok(())
+    }
+}
+
+// Run a binary operation at the wider kind of its two arguments: i64 when
+// both are small integers and the result does not overflow, exact
+// rationals while neither is a float, and floats otherwise
+fn promote(
+    name: &str,
+    a: &Num,
+    b: &Num,
+    small: fn(i64, i64) -> Option<i64>,
+    exact: fn(&BigRational, &BigRational) -> BigRational,
+    inexact: fn(f64, f64) -> f64,
+) -> Result<Num, FelispErr> {
+    if let (Num::Int(x), Num::Int(y)) = (a, b) {
+        if let Some(res) = small(*x, *y) {
+            return Ok(Num::Int(res));
+        }
+    }
+    match (a.to_ratio(), b.to_ratio()) {
+        (Some(x), Some(y)) => Ok(from_ratio(exact(&x, &y))),
+        _ => float_result(name, inexact(a.to_f64(), b.to_f64())),
+    }
+}
+
+pub fn compare(a: &Num, b: &Num) -> Ordering {
+    match (a, b) {
+        (Num::Int(x), Num::Int(y)) => x.cmp(y),
+        _ => match (a.to_ratio(), b.to_ratio()) {
+            (Some(x), Some(y)) => x.cmp(&y),
+            _ => a
+                .to_f64()
+                .partial_cmp(&b.to_f64())
+                .unwrap_or(Ordering::Equal),
+        },
+    }
+}
+
+pub fn add(a: &Num, b: &Num) -> Result<Num, FelispErr> {
+    promote("+", a, b, i64::checked_add, |x, y| x + y, |x, y| x + y)
+}
+
+pub fn sub(a: &Num, b: &Num) -> Result<Num, FelispErr> {
+    promote("-", a, b, i64::checked_sub, |x, y| x - y, |x, y| x - y)
+}
+
+pub fn mul(a: &Num, b: &Num) -> Result<Num, FelispErr> {
+    promote("*", a, b, i64::checked_mul, |x, y| x * y, |x, y| x * y)
+}
+
+// Dividing exact numbers gives an exact rational
+pub fn div(a: &Num, b: &Num) -> Result<Num, FelispErr> {
+    ensure_non_zero_divisor("/", b)?;
+    let small = |x: i64, y: i64| match x.checked_rem(y) {
+        Some(0) => x.checked_div(y),
+        _ => None,
+    };
+    promote("/", a, b, small, |x, y| x / y, |x, y| x / y)
+}
+
+// Integer division, truncating towards zero
+pub fn quot(a: &Num, b: &Num) -> Result<Num, FelispErr> {
+    ensure_non_zero_divisor("quot", b)?;
+    promote(
+        "quot",
+        a,
+        b,
+        i64::checked_div,
+        |x, y| (x / y).trunc(),
+        |x, y| (x / y).trunc(),
+    )
+}
+
+// The remainder takes the sign of the dividend
+pub fn rem(a: &Num, b: &Num) -> Result<Num, FelispErr> {
+    ensure_non_zero_divisor("rem", b)?;
+    promote(
+        "rem",
+        a,
+        b,
+        i64::checked_rem,
+        |x, y| x - y * (x / y).trunc(),
+        |x, y| x % y,
+    )
+}
+
+// The modulo takes the sign of the divisor
+pub fn modulo(a: &Num, b: &Num) -> Result<Num, FelispErr> {
+    ensure_non_zero_divisor("mod", b)?;
+    let r = rem(a, b)?;
+    if !r.is_zero() && r.is_negative() != b.is_negative() {
+        add(&r, b)
+    } else {
+        Ok(r)
+    }
+}
+
+pub fn neg(a: &Num) -> Result<Num, FelispErr> {
+    sub(&Num::Int(0), a)
+}
+
+pub fn abs(a: &Num) -> Result<Num, FelispErr> {
+    if a.is_negative() {
+        neg(a)
+    } else {
+        Ok(a.clone())
+    }
+}
+
+// The most bits an exact power may take, about 315,000 decimal digits.
+// Anything bigger would take long enough to compute that Ctrl-C, which is
+// only checked between evaluation steps, could not stop it.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+// An exact number raised to an integer power stays exact
+pub fn pow(base: &Num, exponent: &Num) -> Result<Num, FelispErr> {
+    if base.is_zero() && exponent.is_negative() {
+        return Err(FelispErr::arithmetic("pow: divide by zero"));
+    }
+    let inexact = || float_result("pow", base.to_f64().powf(exponent.to_f64()));
+    let y = match exponent {
+        Num::Int(y) => *y,
+        _ => return inexact(),
+    };
+    let n = y.unsigned_abs();
+    let power = match base {
+        Num::Int(x) => BigRational::from_integer(int_pow(&BigInt::from(*x), n)?),
+        Num::Big(x) => BigRational::from_integer(int_pow(x, n)?),
+        // Powers of a reduced fraction stay reduced
+        Num::Ratio(r) => BigRational::new_raw(int_pow(r.numer(), n)?, int_pow(r.denom(), n)?),
+        Num::Float(_) => return inexact(),
+    };
+    Ok(from_ratio(if y < 0 { power.recip() } else { power }))
+}
+
+fn int_pow(x: &BigInt, n: u64) -> Result<BigInt, FelispErr> {
+    // 0, 1 and -1 keep their size whatever the power
+    if x.magnitude() <= &BigUint::from(1u32) {
+        return Ok(match n {
+            0 => BigInt::from(1),
+            _ if x.is_negative() && n & 1 == 0 => BigInt::from(1),
+            _ => x.clone(),
+        });
+    }
+    if x.bits().saturating_mul(n) > MAX_POW_BITS {
+        return Err(FelispErr::arithmetic("pow: result too large"));
+    }
+    Ok(x.pow(n as u32))
+}
+
+// Rounding an exact number gives an integer, rounding a float keeps it a
+// float
+pub fn round_with(
+    name: &str,
+    a: &Num,
+    exact: fn(&BigRational) -> BigRational,
+    inexact: fn(f64) -> f64,
+) -> Result<Num, FelispErr> {
+    match a {
+        Num::Float(x) => float_result(name, inexact(*x)),
+        _ => Ok(from_ratio(exact(&a.to_ratio().unwrap_or_default()))),
+    }
+}
+
+// Truncate towards zero into an integer
+pub fn to_int(a: &Num) -> Result<Num, FelispErr> {
+    match a {
+        Num::Float(x) => BigInt::from_f64(x.trunc())
+            .map(from_big)
+            .ok_or_else(|| overflow("int")),
+        _ => round_with("int", a, BigRational::trunc, f64::trunc),
+    }
+}
+
+pub fn to_float(a: &Num) -> Result<Num, FelispErr> {
+    float_result("float", a.to_f64())
+}
+
+// Read a number token, integers first so that `007` is the integer 7,
+// then `n/d` rationals, and only tokens with a `.` or an exponent become
+// floats. Returns None when the token is not a number at all.
+pub fn parse_number(token: &str) -> Option<Result<FelispExp, FelispErr>> {
+    let is_integer = |t: &str| {
+        let digits = t.strip_prefix(|c| c == '+' || c == '-').unwrap_or(t);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    };
+    if is_integer(token) {
+        let i: BigInt = token.trim_start_matches('+').parse().ok()?;
+        return Some(Ok(from_big(i).into_exp()));
+    }
+    if let Some((n, d)) = token.split_once('/') {
+        if !is_integer(n) || d.is_empty() || !d.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let n: BigInt = n.trim_start_matches('+').parse().ok()?;
+        let d: BigInt = d.parse().ok()?;
+        if d.is_zero() {
+            return Some(Err(FelispErr::read(format!(
+                "rational literal {} divides by zero",
+                token
+            ))));
+        }
+        return Some(Ok(from_ratio(BigRational::new(n, d)).into_exp()));
+    }
+    let digits = token.strip_prefix(|c| c == '+' || c == '-').unwrap_or(token);
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let x: f64 = token.parse().ok()?;
+    Some(
+        float_result("read", x)
+            .map(Num::into_exp)
+            .map_err(|_| FelispErr::read(format!("float literal {} out of range", token))),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(token: &str) -> String {
+        match parse_number(token) {
+            Some(Ok(exp)) => exp.to_string(),
+            Some(Err(err)) => err.to_string(),
+            None => "not a number".to_string(),
+        }
+    }
+
+    fn num(token: &str) -> Num {
+        match parse_number(token) {
+            Some(Ok(exp)) => Num::from_exp(&exp).unwrap(),
+            _ => panic!("not a number: {}", token),
+        }
+    }
+
+    fn show(res: Result<Num, FelispErr>) -> String {
+        match res {
+            Ok(n) => n.into_exp().to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(read("007"), "7");
+        assert_eq!(read("-12"), "-12");
+        assert_eq!(read("+12"), "12");
+        assert_eq!(read("1e3"), "1000.0");
+        assert_eq!(read("1.5"), "1.5");
+        assert_eq!(read(".5"), "0.5");
+        assert_eq!(read("3."), "3.0");
+        assert_eq!(read("inf"), "not a number");
+        assert_eq!(read("-"), "not a number");
+        assert_eq!(read("/"), "not a number");
+        assert_eq!(read("1e999"), "float literal 1e999 out of range");
+        assert_eq!(read("99999999999999999999"), "99999999999999999999");
+        assert_eq!(read("2/4"), "1/2");
+        assert_eq!(read("-6/3"), "-2");
+        assert_eq!(read("1/0"), "rational literal 1/0 divides by zero");
+        assert_eq!(read("1/-2"), "not a number");
+    }
+
+    #[test]
+    fn test_promotion() {
+        assert_eq!(show(add(&num("1"), &num("2"))), "3");
+        assert_eq!(show(add(&num("1"), &num("2.0"))), "3.0");
+        assert_eq!(show(add(&num("1/3"), &num("2/3"))), "1");
+        assert_eq!(show(add(&num("1/3"), &num("0.5"))), "0.8333333333333333");
+        assert_eq!(show(div(&num("6"), &num("3"))), "2");
+        assert_eq!(show(div(&num("7"), &num("2"))), "7/2");
+        assert_eq!(show(div(&num("7.0"), &num("2"))), "3.5");
+        assert!(num("1") == num("1.0"));
+        assert!(num("1") < num("3/2"));
+        assert!(num("1/3") < num("0.34"));
+        assert!(num("99999999999999999999") > num("1"));
+    }
+
+    #[test]
+    fn test_bignum_overflow() {
+        assert_eq!(show(add(&Num::Int(i64::MAX), &Num::Int(1))), "9223372036854775808");
+        assert_eq!(show(mul(&Num::Int(i64::MAX), &Num::Int(2))), "18446744073709551614");
+        assert_eq!(show(neg(&Num::Int(i64::MIN))), "9223372036854775808");
+        assert_eq!(show(div(&Num::Int(i64::MIN), &Num::Int(-1))), "9223372036854775808");
+        assert_eq!(show(pow(&num("2"), &num("100"))), "1267650600228229401496703205376");
+        assert_eq!(show(sub(&num("9223372036854775808"), &num("1"))), "9223372036854775807");
+        match sub(&num("9223372036854775808"), &num("1")).unwrap() {
+            Num::Int(_) => (),
+            other => panic!("expected a demoted integer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_exact_ops() {
+        assert_eq!(show(quot(&num("-7"), &num("2"))), "-3");
+        assert_eq!(show(rem(&num("-7"), &num("3"))), "-1");
+        assert_eq!(show(modulo(&num("-7"), &num("3"))), "2");
+        assert_eq!(show(modulo(&num("7"), &num("-3"))), "-2");
+        assert_eq!(show(modulo(&num("7/2"), &num("1"))), "1/2");
+        assert_eq!(show(pow(&num("2/3"), &num("2"))), "4/9");
+        assert_eq!(show(pow(&num("2"), &num("-2"))), "1/4");
+        assert_eq!(show(pow(&num("4"), &num("0.5"))), "2.0");
+        assert_eq!(show(abs(&num("-1/2"))), "1/2");
+        assert_eq!(show(to_int(&num("-7/2"))), "-3");
+        assert_eq!(show(to_int(&num("1e20"))), "100000000000000000000");
+        assert_eq!(show(to_float(&num("1/4"))), "0.25");
+        assert_eq!(show(round_with("round", &num("5/2"), BigRational::round, f64::round)), "3");
+        assert_eq!(show(round_with("floor", &num("-1/2"), BigRational::floor, f64::floor)), "-1");
+    }
+
+    #[test]
+    fn test_cents_stay_exact() {
+        let fee = mul(&num("1999/100"), &num("3/100")).unwrap();
+        assert_eq!(show(Ok(fee.clone())), "5997/10000");
+        let total = add(&fee, &num("1/10")).unwrap();
+        assert_eq!(show(add(&total, &num("2/10"))), "8997/10000");
+    }
+
+    #[test]
+    fn test_pow_size_cap() {
+        assert_eq!(show(pow(&num("2"), &num("200000000"))), "pow: result too large");
+        assert_eq!(show(pow(&num("2/3"), &num("-2000000"))), "pow: result too large");
+        assert_eq!(show(pow(&num("2"), &num("1000000"))), "pow: result too large");
+        assert_eq!(pow(&num("2"), &num("1000000")).unwrap_err().kind.name(), "arithmetic-error");
+        assert_eq!(show(pow(&num("1"), &num("99999999999"))), "1");
+        assert_eq!(show(pow(&num("-1"), &num("99999999999"))), "-1");
+        assert_eq!(show(pow(&num("-1"), &num("-4"))), "1");
+        assert_eq!(show(pow(&num("0"), &num("0"))), "1");
+        assert_eq!(show(pow(&num("-2/3"), &num("-3"))), "-27/8");
+        let big = pow(&num("2"), &num("100000")).unwrap();
+        assert_eq!(show(pow(&big, &num("10"))).len(), 301030);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(show(div(&num("1"), &num("0"))), "/: divide by zero");
+        assert_eq!(show(div(&num("1"), &num("0/5"))), "/: divide by zero");
+        assert_eq!(show(div(&num("1.0"), &num("0.0"))), "/: divide by zero");
+        assert_eq!(show(pow(&num("0"), &num("-1"))), "pow: divide by zero");
+        assert_eq!(show(pow(&num("2"), &num("99999999999"))), "pow: result too large");
+        assert_eq!(show(to_float(&pow(&num("10"), &num("400")).unwrap())), "float: result out of range");
+    }
+}
@@ -1,80 +1,306 @@
-use std::num::ParseFloatError;
 use std::rc::Rc;
 
 use crate::lib::data::*;
+use crate::lisp_core::numeric::{parse_number, Num};
+
+use crate::lisp_core::tokenizer::{Span, Token};
 
 // Now, convert this AST expression into a felisp expression
 
-pub fn parse(tokens: &[String]) -> Result<(FelispExp, &[String]), FelispErr> {
+// Source locations of a parsed form, mirroring its shape: the node for a
+// list spans its parens and has one child per element, atoms are leaves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+pub fn parse(tokens: &[Token]) -> Result<(FelispExp, &[Token]), FelispErr> {
+    let (exp, _, rest) = parse_spanned(tokens)?;
+    Ok((exp, rest))
+}
+
+pub fn parse_spanned(tokens: &[Token]) -> Result<(FelispExp, SpanTree, &[Token]), FelispErr> {
+    let tokens = skip_datum_comments(tokens)?;
     let (token, rest) = tokens
         .split_first()
-        .ok_or(FelispErr::Reason("Could not get token".to_string()))?;
-    match &token[..] {
-        "(" => read_seq(rest),
-        ")" => Err(FelispErr::Reason("unexpected `)`".to_string())),
-        _ => Ok((parse_atom(token), rest)),
+        .ok_or_else(|| FelispErr::read("Could not get token"))?;
+    match &token.text[..] {
+        "(" => read_seq(token, rest),
+        ")" => Err(token.error("unexpected `)`")),
+        "'" | "`" | "," | ",@" => read_prefixed(token, rest),
+        _ => {
+            let exp = parse_atom(&token.text).map_err(|e| token.error(&e.message()))?;
+            let leaf = SpanTree {
+                span: token.span,
+                children: vec![],
+            };
+            Ok((exp, leaf, rest))
+        }
     }
 }
 
-pub fn read_seq(tokens: &[String]) -> Result<(FelispExp, &[String]), FelispErr> {
+pub fn read_seq<'a>(
+    open: &Token,
+    tokens: &'a [Token],
+) -> Result<(FelispExp, SpanTree, &'a [Token]), FelispErr> {
     let mut res: Vec<FelispExp> = vec![];
+    let mut children: Vec<SpanTree> = vec![];
     let mut xs = tokens;
     loop {
         // infinite loop here
+        xs = skip_datum_comments(xs)?;
         let (next_token, rest) = xs
             .split_first()
-            .ok_or(FelispErr::Reason("could not find closing `)`".to_string()))?;
-        if next_token == ")" {
-            return Ok((FelispExp::List(res), rest)); // skip `)`, head to the token after
+            .ok_or_else(|| open.error("could not find closing `)`"))?;
+        if next_token.text == ")" {
+            let close = next_token.span;
+            let span = Span {
+                len: close.offset + close.len - open.span.offset,
+                ..open.span
+            };
+            // skip `)`, head to the token after
+            return Ok((FelispExp::List(res), SpanTree { span, children }, rest));
         }
-        let (exp, new_xs) = parse(&xs)?;
+        let (exp, tree, new_xs) = parse_spanned(xs)?;
         res.push(exp);
+        children.push(tree);
         xs = new_xs;
     }
 }
 
-// convert each atom into a number or a symbol
-pub fn parse_atom(token: &str) -> FelispExp {
-    match token.as_ref() {
-        "true" => FelispExp::Bool(true),
-        "false" => FelispExp::Bool(false),
-        _ => {
-            let potential_float: Result<f64, ParseFloatError> = token.parse();
-            match potential_float {
-                Ok(v) => FelispExp::Number(v),
-                Err(_) => FelispExp::Symbol(token.to_string().clone()),
+// Expand a reader prefix into the form it abbreviates, so that `'x` reads
+// as `(quote x)`, `` `x `` as `(quasiquote x)`, `,x` as `(unquote x)` and
+// `,@x` as `(unquote-splicing x)`
+pub fn read_prefixed<'a>(
+    prefix: &Token,
+    tokens: &'a [Token],
+) -> Result<(FelispExp, SpanTree, &'a [Token]), FelispErr> {
+    let name = match &prefix.text[..] {
+        "'" => "quote",
+        "`" => "quasiquote",
+        "," => "unquote",
+        _ => "unquote-splicing",
+    };
+    let tokens = skip_datum_comments(tokens)?;
+    match tokens.first() {
+        Some(next) if next.text != ")" => (),
+        _ => return Err(prefix.error(&format!("expected a form after `{}`", prefix.text))),
+    }
+    let (exp, tree, rest) = parse_spanned(tokens)?;
+    let span = Span {
+        len: tree.span.offset + tree.span.len - prefix.span.offset,
+        ..prefix.span
+    };
+    let leaf = SpanTree {
+        span: prefix.span,
+        children: vec![],
+    };
+    let exp = FelispExp::List(vec![FelispExp::Symbol(name.to_string()), exp]);
+    Ok((exp, SpanTree { span, children: vec![leaf, tree] }, rest))
+}
+
+// Drop every `#_` along with the form that follows it
+pub fn skip_datum_comments(tokens: &[Token]) -> Result<&[Token], FelispErr> {
+    let mut xs = tokens;
+    while let Some((token, rest)) = xs.split_first() {
+        if token.text != "#_" {
+            break;
+        }
+        match rest.first() {
+            Some(next) if next.text != ")" => {
+                let (_, _, new_xs) = parse_spanned(rest)?;
+                xs = new_xs;
+            }
+            _ => return Err(token.error("expected a form after `#_`")),
+        }
+    }
+    Ok(xs)
+}
+
+// convert each atom into a string, a number or a symbol
+pub fn parse_atom(token: &str) -> Result<FelispExp, FelispErr> {
+    match token {
+        "nil" => Ok(FelispExp::Nil),
+        "true" => Ok(FelispExp::Bool(true)),
+        "false" => Ok(FelispExp::Bool(false)),
+        _ if token.starts_with('"') => Ok(FelispExp::Str(parse_string_literal(token)?)),
+        _ => match parse_number(token) {
+            Some(number) => number,
+            None => Ok(FelispExp::Symbol(token.to_string())),
+        },
+    }
+}
+
+// Decode a quoted string token as produced by the tokenizer, handling the
+// `\n`, `\t`, `\"`, `\\` and `\u{...}` escapes
+pub fn parse_string_literal(token: &str) -> Result<String, FelispErr> {
+    let inner = token
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| FelispErr::read(format!("malformed string literal {}", token)))?;
+    let mut res = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('"') => res.push('"'),
+            Some('\\') => res.push('\\'),
+            Some('u') => {
+                let rest = chars.as_str();
+                let end = rest
+                    .find('}')
+                    .filter(|_| rest.starts_with('{'))
+                    .ok_or_else(|| {
+                        FelispErr::read(format!("expected `\\u{{...}}` in {}", token))
+                    })?;
+                let hex = &rest[1..end];
+                let ch = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| {
+                        FelispErr::read(format!("invalid unicode escape `\\u{{{}}}`", hex))
+                    })?;
+                res.push(ch);
+                chars = rest[end + 1..].chars();
+            }
+            Some(other) => {
+                return Err(FelispErr::read(format!(
+                    "unknown escape `\\{}` in {}",
+                    other, token
+                )))
             }
+            None => return Err(FelispErr::read(format!("unterminated string {}", token))),
         }
     }
+    Ok(res)
 }
 
 
 
-// Helper function that enforces all FelispExp's that we receive are floats
-pub fn parse_list_of_floats(args: &[FelispExp]) -> Result<Vec<f64>, FelispErr> {
-    args.iter().map(|x| parse_single_float(x)).collect() // no ; since return expression
+// Helper function that enforces all FelispExp's that we receive are numbers
+pub fn parse_list_of_nums(args: &[FelispExp]) -> Result<Vec<Num>, FelispErr> {
+    args.iter().map(Num::from_exp).collect() // no ; since return expression
 }
 
-pub fn parse_single_float(exp: &FelispExp) -> Result<f64, FelispErr> {
+pub fn parse_single_int(exp: &FelispExp) -> Result<i64, FelispErr> {
     match exp {
-        FelispExp::Number(num) => Ok(*num),
-        _ => Err(FelispErr::Reason("expected a number".to_string())),
+        FelispExp::Int(num) => Ok(*num),
+        _ => Err(FelispErr::type_error("an integer", exp)),
+    }
+}
+
+pub fn parse_single_string(exp: &FelispExp) -> Result<String, FelispErr> {
+    match exp {
+        FelispExp::Str(s) => Ok(s.clone()),
+        _ => Err(FelispErr::type_error("a string", exp)),
     }
 }
 
 pub fn parse_list_of_symbol_strings(form: Rc<FelispExp>) -> Result<Vec<String>, FelispErr> {
     let list = match form.as_ref() {
         FelispExp::List(s) => Ok(s.clone()),
-        _ => Err(FelispErr::Reason(
-            "expected args form to be a list".to_string(),
-        )),
+        _ => Err(FelispErr::syntax("expected args form to be a list")),
     }?;
     list.iter()
         .map(|x| match x {
             FelispExp::Symbol(s) => Ok(s.clone()),
-            _ => Err(FelispErr::Reason(
-                "expected symbols in the argument list".to_string(),
-            )),
+            _ => Err(FelispErr::syntax("expected symbols in the argument list")),
         })
         .collect()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lisp_core::tokenizer::tokenize;
+
+    fn message(err: FelispErr) -> String {
+        err.to_string()
+    }
+
+    fn read(src: &str) -> FelispExp {
+        let tokens = tokenize(src.to_string()).unwrap();
+        parse(&tokens).unwrap().0
+    }
+
+    #[test]
+    fn test_parse_string_escapes() {
+        match read(r#""a\tb\n\"c\" \\ \u{1F525}""#) {
+            FelispExp::Str(s) => assert_eq!(s, "a\tb\n\"c\" \\ 🔥"),
+            other => panic!("expected a string, got {}", other),
+        }
+        assert!(parse_atom(r#""bad \q""#).is_err());
+        assert!(parse_atom(r#""bad \u{zz}""#).is_err());
+        assert!(parse_atom(r#""bad \u{41""#).is_err());
+    }
+
+    #[test]
+    fn test_display_string_round_trip() {
+        let exp = read(r#"(insert t "hello world" "x\"y\\z\n")"#);
+        assert_eq!(exp.to_string(), r#"(insert t "hello world" "x\"y\\z\n")"#);
+    }
+
+    #[test]
+    fn test_parse_errors_point_at_source() {
+        let tokens = tokenize("(defn x\n  (+ 1 2)))".to_string()).unwrap();
+        let (_, rest) = parse(&tokens).unwrap();
+        assert_eq!(
+            parse(rest).err().map(message).unwrap(),
+            "unexpected `)` at line 2, col 11\n  2 |   (+ 1 2)))\n    |           ^"
+        );
+
+        let tokens = tokenize("(defn x\n  (+ 1 2)".to_string()).unwrap();
+        assert_eq!(
+            parse(&tokens).err().map(message).unwrap(),
+            "could not find closing `)` at line 1, col 1\n  1 | (defn x\n    | ^"
+        );
+    }
+
+    #[test]
+    fn test_datum_comments() {
+        assert_eq!(read("(+ 1 #_(* 2 3) #_ #_ 4 5 6)").to_string(), "(+ 1 6)");
+        assert_eq!(read("#_ignored (kept)").to_string(), "(kept)");
+        let tokens = tokenize("(a #_)".to_string()).unwrap();
+        assert_eq!(
+            parse(&tokens).err().map(message).unwrap(),
+            "expected a form after `#_` at line 1, col 4\n  1 | (a #_)\n    |    ^^"
+        );
+    }
+
+    #[test]
+    fn test_quote_prefixes() {
+        assert_eq!(read("'a").to_string(), "(quote a)");
+        assert_eq!(
+            read("`(a ,b ,@c)").to_string(),
+            "(quasiquote (a (unquote b) (unquote-splicing c)))"
+        );
+        assert_eq!(read("''()").to_string(), "(quote (quote ()))");
+        assert_eq!(read("'#_x y").to_string(), "(quote y)");
+        let tokens = tokenize("(a ')".to_string()).unwrap();
+        assert_eq!(
+            parse(&tokens).err().map(message).unwrap(),
+            "expected a form after `'` at line 1, col 4\n  1 | (a ')\n    |    ^"
+        );
+        let tokens = tokenize("'(b c)".to_string()).unwrap();
+        let (_, tree, _) = parse_spanned(&tokens).unwrap();
+        assert_eq!((tree.span.offset, tree.span.len), (0, 6));
+        assert_eq!(tree.children[1].span.offset, 1);
+    }
+
+    #[test]
+    fn test_span_tree() {
+        let tokens = tokenize("(a (b c))".to_string()).unwrap();
+        let (_, tree, _) = parse_spanned(&tokens).unwrap();
+        assert_eq!((tree.span.offset, tree.span.len), (0, 9));
+        assert_eq!(tree.children.len(), 2);
+        let inner = &tree.children[1];
+        assert_eq!((inner.span.offset, inner.span.len, inner.span.col), (3, 5, 4));
+        assert_eq!(inner.children[1].span.offset, 6);
+    }
+}
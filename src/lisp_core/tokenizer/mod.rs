@@ -1,10 +1,370 @@
 // Create a tokenizer that takes a felisp expression in string
 // and converts it to an AST
 
-pub fn tokenize(expr: String) -> Vec<String> {
-    expr.replace("(", " ( ")
-        .replace(")", " ) ")
-        .split_whitespace()
-        .map(|x| x.to_string())
-        .collect()
+use std::rc::Rc;
+
+use crate::lib::data::*;
+
+// Where a token sits in its source: byte offset and length, plus the
+// 1-based line and column (in chars) of its first character.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub text: String,
+    pub span: Span,
+    pub source: Rc<str>,
+}
+
+impl Token {
+    pub fn error(&self, msg: &str) -> FelispErr {
+        span_error(&self.source, self.span, msg)
+    }
+}
+
+// Build an error pointing at `span`, with the offending source line and a
+// caret underline, e.g.
+//
+//   unexpected `)` at line 1, col 8
+//     1 | (+ 1 2))
+//       |        ^
+pub fn span_error(source: &str, span: Span, msg: &str) -> FelispErr {
+    FelispErr::read(span_message(source, span, msg))
+}
+
+// Like `span_error`, for input that stops before the string or block
+// comment at `span` is closed, and so may go on in more input
+fn incomplete_error(source: &str, span: Span, msg: &str) -> FelispErr {
+    FelispErr::new(FelispErrKind::Incomplete(span_message(source, span, msg)))
+}
+
+fn span_message(source: &str, span: Span, msg: &str) -> String {
+    let line = source.lines().nth(span.line - 1).unwrap_or("");
+    let line_chars = line.chars().count();
+    let width = source[span.offset..span.offset + span.len].chars().count();
+    let width = width.min(line_chars.saturating_sub(span.col - 1)).max(1);
+    let gutter = " ".repeat(span.line.to_string().len());
+    format!(
+        "{} at line {}, col {}\n  {} | {}\n  {} | {}{}",
+        msg,
+        span.line,
+        span.col,
+        span.line,
+        line,
+        gutter,
+        " ".repeat(span.col - 1),
+        "^".repeat(width)
+    )
+}
+
+#[derive(Clone, Copy)]
+struct Pos {
+    offset: usize,
+    line: usize,
+    col: usize,
+}
+
+struct Cursor<'a> {
+    src: &'a str,
+    pos: Pos,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos.offset..].chars().next()
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.src[self.pos.offset..].starts_with(prefix)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos.offset += c.len_utf8();
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
+        }
+        Some(c)
+    }
+
+    fn span_from(&self, start: Pos) -> Span {
+        Span {
+            offset: start.offset,
+            len: self.pos.offset - start.offset,
+            line: start.line,
+            col: start.col,
+        }
+    }
+}
+
+// Parens are always tokens of their own, whitespace separates everything
+// else. A double-quoted string literal is kept as a single token, quotes
+// and escapes included, so that `parse_atom` can decode it.
+// `;` line comments and `#| ... |#` block comments are dropped here, while
+// the `#_` datum comment is a token since only the parser knows how far
+// the next form reaches. For the same reason the `'`, `` ` ``, `,` and `,@`
+// reader prefixes are tokens of their own.
+pub fn tokenize(expr: String) -> Result<Vec<Token>, FelispErr> {
+    let source: Rc<str> = Rc::from(expr);
+    let mut tokens: Vec<Token> = vec![];
+    let mut cursor = Cursor {
+        src: &source,
+        pos: Pos {
+            offset: 0,
+            line: 1,
+            col: 1,
+        },
+    };
+    while let Some(c) = cursor.peek() {
+        let start = cursor.pos;
+        match c {
+            '(' | ')' | '\'' | '`' => {
+                cursor.bump();
+            }
+            ',' => {
+                cursor.bump();
+                if cursor.peek() == Some('@') {
+                    cursor.bump();
+                }
+            }
+            '"' => scan_string(&mut cursor, &source)?,
+            ';' => {
+                while !matches!(cursor.peek(), None | Some('\n')) {
+                    cursor.bump();
+                }
+                continue;
+            }
+            '#' if cursor.starts_with("#|") => {
+                skip_block_comment(&mut cursor, &source)?;
+                continue;
+            }
+            '#' if cursor.starts_with("#_") => {
+                cursor.bump();
+                cursor.bump();
+            }
+            c if c.is_whitespace() => {
+                cursor.bump();
+                continue;
+            }
+            _ => {
+                while let Some(next) = cursor.peek() {
+                    if next.is_whitespace() || "()\";".contains(next) {
+                        break;
+                    }
+                    cursor.bump();
+                }
+            }
+        }
+        let span = cursor.span_from(start);
+        tokens.push(Token {
+            text: source[span.offset..span.offset + span.len].to_string(),
+            span,
+            source: source.clone(),
+        });
+    }
+    Ok(tokens)
+}
+
+// Whether `src` stops in the middle of a form, so that a REPL should read
+// another line before evaluating it: inside a string or a block comment,
+// with a paren left open, or right after a reader prefix. Input that no
+// more lines could fix, like a `)` with nothing to close, is an error.
+pub fn needs_more_input(src: &str) -> Result<bool, FelispErr> {
+    let tokens = match tokenize(src.to_string()) {
+        Ok(tokens) => tokens,
+        Err(FelispErr { kind: FelispErrKind::Incomplete(_), .. }) => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    let mut depth = 0;
+    for token in &tokens {
+        match &token.text[..] {
+            "(" => depth += 1,
+            ")" if depth == 0 => return Err(token.error("unexpected `)`")),
+            ")" => depth -= 1,
+            _ => (),
+        }
+    }
+    let dangling = matches!(
+        tokens.last().map(|t| &t.text[..]),
+        Some("'") | Some("`") | Some(",") | Some(",@") | Some("#_")
+    );
+    Ok(depth > 0 || dangling)
+}
+
+// Consume a `#| ... |#` block comment, which may contain nested ones
+fn skip_block_comment(cursor: &mut Cursor, source: &str) -> Result<(), FelispErr> {
+    let mut openings = vec![];
+    loop {
+        if cursor.starts_with("#|") {
+            openings.push(cursor.pos);
+            cursor.bump();
+            cursor.bump();
+        } else if cursor.starts_with("|#") {
+            openings.pop();
+            cursor.bump();
+            cursor.bump();
+            if openings.is_empty() {
+                return Ok(());
+            }
+        } else if cursor.bump().is_none() {
+            let start = openings[openings.len() - 1];
+            let span = Span { len: 2, ..cursor.span_from(start) };
+            return Err(incomplete_error(source, span, "unterminated block comment"));
+        }
+    }
+}
+
+// Consume a string literal, checking its escapes so that errors point at
+// the offending character rather than at the whole token
+fn scan_string(cursor: &mut Cursor, source: &str) -> Result<(), FelispErr> {
+    let start = cursor.pos;
+    cursor.bump();
+    loop {
+        let escape_start = cursor.pos;
+        match cursor.bump() {
+            None => {
+                let span = Span { len: 1, ..cursor.span_from(start) };
+                return Err(incomplete_error(source, span, "unterminated string"));
+            }
+            Some('"') => return Ok(()),
+            Some('\\') => match cursor.bump() {
+                Some('n') | Some('t') | Some('"') | Some('\\') => (),
+                Some('u') => {
+                    let mut hex = String::new();
+                    let opened = cursor.peek() == Some('{');
+                    if opened {
+                        cursor.bump();
+                        while let Some(h) = cursor.peek().filter(|h| h.is_ascii_hexdigit()) {
+                            hex.push(h);
+                            cursor.bump();
+                        }
+                    }
+                    let valid = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .is_some();
+                    if !opened || !valid || cursor.bump() != Some('}') {
+                        let span = cursor.span_from(escape_start);
+                        return Err(span_error(source, span, "invalid unicode escape"));
+                    }
+                }
+                Some(_) => {
+                    let span = cursor.span_from(escape_start);
+                    return Err(span_error(source, span, "unknown escape"));
+                }
+                None => {
+                    let span = Span { len: 1, ..cursor.span_from(start) };
+                    return Err(incomplete_error(source, span, "unterminated string"));
+                }
+            },
+            Some(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn texts(src: &str) -> Vec<String> {
+        tokenize(src.to_string())
+            .unwrap()
+            .into_iter()
+            .map(|t| t.text)
+            .collect()
+    }
+
+    fn message(err: FelispErr) -> String {
+        err.to_string()
+    }
+
+    #[test]
+    fn test_tokenize_parens() {
+        assert_eq!(
+            texts("(+ 1 (- 2 3))"),
+            vec!["(", "+", "1", "(", "-", "2", "3", ")", ")"]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_strings() {
+        assert_eq!(
+            texts(r#"(insert t "hello world" "a \"(b)\"")"#),
+            vec!["(", "insert", "t", "\"hello world\"", r#""a \"(b)\"""#, ")"]
+        );
+        assert!(tokenize("\"no end".to_string()).is_err());
+        assert!(tokenize("\"trailing \\".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let src = "; setup\n(insert t ; the table\n \"a;b\" #| note #| nested |# |# x) #_(y)";
+        assert_eq!(
+            texts(src),
+            vec!["(", "insert", "t", "\"a;b\"", "x", ")", "#_", "(", "y", ")"]
+        );
+        assert_eq!(texts("a;b"), vec!["a"]);
+        let msg = message(tokenize("(a #| x #| y |#\n".to_string()).unwrap_err());
+        assert_eq!(msg, "unterminated block comment at line 1, col 4\n  1 | (a #| x #| y |#\n    |    ^^");
+    }
+
+    #[test]
+    fn test_tokenize_quote_prefixes() {
+        assert_eq!(
+            texts("'a `(b ,c ,@(d)) '()"),
+            vec!["'", "a", "`", "(", "b", ",", "c", ",@", "(", "d", ")", ")", "'", "(", ")"]
+        );
+        assert_eq!(texts("don't"), vec!["don't"]);
+    }
+
+    #[test]
+    fn test_needs_more_input() {
+        let more = |src: &str| needs_more_input(src).map_err(|e| e.message());
+        assert_eq!(more("(defn sq\n  (fn (x)"), Ok(true));
+        assert_eq!(more("(insert t \"multi\nline"), Ok(true));
+        assert_eq!(more("#| notes\n"), Ok(true));
+        assert_eq!(more("(a) '"), Ok(true));
+        assert_eq!(more("(a ; )\n"), Ok(true));
+        assert_eq!(more("(defn sq\n  (fn (x) (* x x)))"), Ok(false));
+        assert_eq!(more("(a) (b)"), Ok(false));
+        assert_eq!(more(""), Ok(false));
+        assert!(more("(a))").unwrap_err().starts_with("unexpected `)` at line 1, col 4"));
+        assert!(more("())(").unwrap_err().starts_with("unexpected `)` at line 1, col 3"));
+        assert!(more("\"bad \\q\"").unwrap_err().starts_with("unknown escape"));
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let tokens = tokenize("(defn x\n  \"é\" 42)".to_string()).unwrap();
+        let spans: Vec<(usize, usize, usize, usize)> = tokens
+            .iter()
+            .map(|t| (t.span.offset, t.span.len, t.span.line, t.span.col))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 1, 1, 1),
+                (1, 4, 1, 2),
+                (6, 1, 1, 7),
+                (10, 4, 2, 3),
+                (15, 2, 2, 7),
+                (17, 1, 2, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_error_points_at_escape() {
+        let msg = message(tokenize("(f\n  \"ok \\q\")".to_string()).unwrap_err());
+        assert_eq!(msg, "unknown escape at line 2, col 7\n  2 |   \"ok \\q\")\n    |       ^^");
+    }
 }
@@ -12,50 +12,86 @@ Env    = dict
 # is a mapping of {variable: value}
 */
 
-use std::fmt;
-use std::io;
-use std::io::{stdin, stdout, Write};
+mod repl;
 
-mod lib;
-use lib::data::{FelispExp, FelispEnv, FelispErr, FelispLambda, Table, Row};
+use std::env;
+use std::io::{self, Read, Write};
+use std::process;
 
-use lib::db::stmt::{execute_select, execute_insert};
+use felisp::lib::data::{FelispErr, FelispErrKind};
+use felisp::{FelispExp, Interpreter};
 
-mod lisp_core;
-use lisp_core::tokenizer::tokenize;
-use lisp_core::parser::*;
-use lisp_core::env::default_env;
-use lisp_core::eval::*;
+const USAGE: &str = "usage: felisp                   start a REPL
+       felisp FILE [ARGS...]     run a script
+       felisp -e EXPR [ARGS...]  evaluate EXPR and print its value
+       felisp - [ARGS...]        run a script read from stdin";
 
-
-// Repl function
-fn parse_eval(expr: String, env: &mut FelispEnv) -> Result<FelispExp, FelispErr> {
-    let (parsed_exp, _) = parse(&tokenize(expr))?;
-    let evaled_exp = eval(&parsed_exp, env)?;
-    Ok(evaled_exp)
+// The exit status for the outcome of running a program, reporting an
+// uncaught error on stderr
+fn status(res: Result<FelispExp, FelispErr>, print: bool) -> i32 {
+    match res {
+        Ok(FelispExp::Nil) => 0,
+        Ok(res) => {
+            if print {
+                println!("{}", res);
+            }
+            0
+        }
+        Err(FelispErr { kind: FelispErrKind::Exit(code), .. }) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
 }
 
-fn slurp_expr() -> String {
-    let mut expr = String::new();
+fn read_stdin() -> Result<String, FelispErr> {
+    let mut src = String::new();
     io::stdin()
-        .read_line(&mut expr)
-        .expect("Failed to read line");
-    expr
+        .read_to_string(&mut src)
+        .map_err(|e| FelispErr::io(format!("could not read stdin: {}", e)))?;
+    Ok(src)
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
     // Lisp layer
-    let env = &mut default_env();
-    loop {
-        println!("Felisp> ");
-        let expr = slurp_expr();
-        match parse_eval(expr, env) {
-            Ok(res) => println!("// 🔥 => {}", res),
-            Err(e) => match e {
-                FelispErr::Reason(msg) => println!("// 🙀 => {}", msg),
-            },
+    let mut interp = Interpreter::new();
+    // The arguments after the program itself are bound to *argv*
+    let code = match args.first().map(String::as_str) {
+        None => {
+            interp.set_global("*argv*", Vec::<String>::new());
+            repl::run(&mut interp)
         }
-    }
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            0
+        }
+        Some("-e") => match args.get(1) {
+            Some(expr) => {
+                interp.set_global("*argv*", args[2..].to_vec());
+                status(interp.eval_str(expr), true)
+            }
+            None => {
+                eprintln!("{}", USAGE);
+                2
+            }
+        },
+        Some("-") => {
+            interp.set_global("*argv*", args[1..].to_vec());
+            let res = read_stdin().and_then(|src| interp.eval_str(&src));
+            status(res, false)
+        }
+        Some(path) => {
+            interp.set_global("*argv*", args[1..].to_vec());
+            status(interp.eval_file(path), false)
+        }
+    };
 
+    // process::exit skips destructors, so drop the interpreter and the
+    // tables it holds and flush what was printed first
+    drop(interp);
+    let _ = io::stdout().flush();
+    process::exit(code);
 }
@@ -0,0 +1,319 @@
+// The interactive loop, with line editing: tab completion of bound symbols
+// and table names, and history kept between sessions
+
+use std::env;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+
+use felisp::lib::data::{FelispEnvRef, FelispErr, FelispErrKind, FelispExp};
+use felisp::lisp_core::eval::{bound_names, interrupt, SPECIAL_FORMS};
+use felisp::lisp_core::tokenizer::needs_more_input;
+use felisp::Interpreter;
+
+// The REPL's own commands, with how to call them
+const COMMANDS: &[(&str, &str)] = &[
+    (":env", ":env"),
+    (":tables", ":tables"),
+    (":doc", ":doc symbol"),
+    (":type", ":type expr"),
+    (":time", ":time expr"),
+    (":load", ":load file"),
+    (":reset", ":reset"),
+];
+
+// Read, evaluate and print until Ctrl-D or (exit), returning the status to
+// exit with
+pub fn run(interp: &mut Interpreter) -> i32 {
+    // Ctrl-C stops the form being evaluated rather than the REPL. At the
+    // prompt the line editor reads it as a key press instead.
+    ctrlc::set_handler(interrupt).expect("could not set the Ctrl-C handler");
+    let mut editor: Editor<FelispHelper, DefaultHistory> =
+        Editor::new().expect("could not start the line editor");
+    editor.set_helper(Some(FelispHelper::new(interp.env().clone())));
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history file until the first session saves one
+        let _ = editor.load_history(path);
+    }
+    'repl: loop {
+        let mut expr = match editor.readline("Felisp> ") {
+            Ok(line) => line,
+            // Ctrl-C drops the input typed so far
+            Err(ReadlineError::Interrupted) => continue,
+            // Ctrl-D, or the end of piped input
+            Err(_) => return 0,
+        };
+        // Keep reading while a form is left open, stopping at end of input.
+        // Input that is already wrong is left for `read` to report.
+        while let Ok(true) = needs_more_input(&expr) {
+            match editor.readline("   ...> ") {
+                Ok(line) => {
+                    expr.push('\n');
+                    expr.push_str(&line);
+                }
+                Err(ReadlineError::Interrupted) => continue 'repl,
+                Err(_) => break,
+            }
+        }
+        if !expr.trim().is_empty() {
+            let _ = editor.add_history_entry(expr.as_str());
+            if let Some(path) = &history {
+                let _ = editor.save_history(path);
+            }
+        }
+        if let Some(res) = meta_command(interp, expr.trim()) {
+            // After a :reset completion has a new env to look in
+            if let Some(helper) = editor.helper_mut() {
+                helper.env = interp.env().clone();
+            }
+            match res {
+                Ok(lines) => lines.iter().for_each(|line| println!("// {}", line)),
+                Err(FelispErr { kind: FelispErrKind::Exit(code), .. }) => return code,
+                Err(e) => println!("// 🙀 => {}", e),
+            }
+            continue;
+        }
+        let forms = match interp.read(&expr) {
+            Ok(forms) => forms,
+            Err(e) => {
+                println!("// 🙀 => {}", e);
+                continue;
+            }
+        };
+        for form in forms {
+            match interp.eval(&form) {
+                Ok(res) => println!("// 🔥 => {}", res),
+                Err(FelispErr { kind: FelispErrKind::Exit(code), .. }) => return code,
+                Err(e) => {
+                    println!("// 🙀 => {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Run `input` if it is one of the COMMANDS, returning the lines to show.
+// Any other input is left to evaluate, keywords included.
+fn meta_command(interp: &mut Interpreter, input: &str) -> Option<Result<Vec<String>, FelispErr>> {
+    let (cmd, arg) = match input.find(char::is_whitespace) {
+        Some(i) => (&input[..i], input[i..].trim()),
+        None => (input, ""),
+    };
+    let res = match (cmd, arg) {
+        (":env", "") => Ok(env_lines(interp)),
+        (":tables", "") => Ok(table_lines(interp)),
+        (":reset", "") => {
+            reset(interp);
+            Ok(vec!["reset to the default env".to_string()])
+        }
+        (":doc", sym) if !sym.is_empty() => doc_lines(interp, sym),
+        (":type", src) if !src.is_empty() => {
+            interp.eval_str(src).map(|res| vec![res.type_name().to_string()])
+        }
+        (":time", src) if !src.is_empty() => {
+            let start = Instant::now();
+            interp.eval_str(src).map(|res| {
+                vec![format!("🔥 => {}", res), format!("took {:.3?}", start.elapsed())]
+            })
+        }
+        (":load", path) if !path.is_empty() => {
+            interp.eval_file(path).map(|res| vec![format!("🔥 => {}", res)])
+        }
+        _ => {
+            let (_, usage) = COMMANDS.iter().find(|(name, _)| *name == cmd)?;
+            Err(FelispErr::syntax(format!("usage: {}", usage)))
+        }
+    };
+    Some(res)
+}
+
+// The bindings of the global frame, with the type of each
+fn env_lines(interp: &Interpreter) -> Vec<String> {
+    let env = interp.env().borrow();
+    let mut names: Vec<&String> = env.data.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| format!("{:<12} {}", name, env.data[name].type_name()))
+        .collect()
+}
+
+fn table_lines(interp: &Interpreter) -> Vec<String> {
+    let env = interp.env().borrow();
+    let mut lines: Vec<String> = env
+        .data
+        .values()
+        .filter_map(|exp| match exp {
+            FelispExp::Table(t) => {
+                let t = t.borrow();
+                Some(format!("{:<12} {} rows, {} pages", t.name, t.num_rows, t.num_pages))
+            }
+            _ => None,
+        })
+        .collect();
+    lines.sort();
+    lines
+}
+
+// Start over from the default env, keeping only *argv*
+fn reset(interp: &mut Interpreter) {
+    let argv = interp.get_global::<FelispExp>("*argv*").ok();
+    *interp = Interpreter::new();
+    if let Some(argv) = argv {
+        interp.set_global("*argv*", argv);
+    }
+}
+
+fn doc_lines(interp: &Interpreter, sym: &str) -> Result<Vec<String>, FelispErr> {
+    if SPECIAL_FORMS.contains(&sym) {
+        return Ok(vec![format!("{}  special form", sym)]);
+    }
+    let exp = interp.get_global::<FelispExp>(sym)?;
+    let lines = match &exp {
+        FelispExp::Func(f) => vec![format!("{}  builtin taking {}", sym, f.arity.describe())],
+        FelispExp::Lambda(lambda) | FelispExp::Macro(lambda) => {
+            let params = match &*lambda.params_exp {
+                FelispExp::List(params) => {
+                    let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                    params.join(" ")
+                }
+                params => params.to_string(),
+            };
+            let mut lines = vec![format!("({} {})  {}", sym, params, exp.type_name())];
+            lines.extend(lambda.doc().map(String::from));
+            lines
+        }
+        _ => vec![format!("{}  {}", sym, exp.type_name())],
+    };
+    Ok(lines)
+}
+
+pub struct FelispHelper {
+    env: FelispEnvRef,
+}
+
+impl FelispHelper {
+    pub fn new(env: FelispEnvRef) -> FelispHelper {
+        FelispHelper { env }
+    }
+}
+
+// `~/.felisp_history`, or none when there is no home directory to keep it in
+pub fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".felisp_history"))
+}
+
+// Where the symbol under the cursor at `pos` starts
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos]
+        .rfind(|c: char| c.is_whitespace() || "()'`,@\"".contains(c))
+        .map_or(0, |i| i + 1)
+}
+
+impl Completer for FelispHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(line, pos);
+        let word = &line[start..pos];
+        let candidates = bound_names(&self.env)
+            .into_iter()
+            .filter(|name| name.starts_with(word))
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for FelispHelper {
+    type Hint = String;
+}
+
+impl Highlighter for FelispHelper {}
+
+impl Validator for FelispHelper {}
+
+impl Helper for FelispHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use felisp::lisp_core::env::default_env;
+    use rustyline::history::DefaultHistory;
+
+    fn command(interp: &mut Interpreter, input: &str) -> Vec<String> {
+        match meta_command(interp, input) {
+            Some(Ok(lines)) => lines,
+            Some(Err(e)) => vec![format!("error: {}", e)],
+            None => vec!["not a command".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let mut interp = Interpreter::new();
+        interp.set_global("*argv*", vec!["a"]);
+        interp
+            .eval_str("(defn sq (fn (x) \"Square x.\" (* x x))) (insert mytable1 1 \"a\" \"a@x\")")
+            .unwrap();
+        assert!(command(&mut interp, ":env").contains(&"sq           fn".to_string()));
+        assert_eq!(command(&mut interp, ":tables"), vec!["mytable1     1 rows, 1 pages"]);
+        assert_eq!(command(&mut interp, ":doc sq"), vec!["(sq x)  fn", "Square x."]);
+        assert_eq!(command(&mut interp, ":doc sqrt"), vec!["sqrt  builtin taking 1 argument"]);
+        assert_eq!(command(&mut interp, ":doc if"), vec!["if  special form"]);
+        assert_eq!(command(&mut interp, ":doc nope"), vec!["error: unbound symbol k='nope'"]);
+        assert_eq!(command(&mut interp, ":type (sq 1.5)"), vec!["float"]);
+        assert_eq!(command(&mut interp, ":time (sq 3)")[0], "🔥 => 9");
+        assert_eq!(command(&mut interp, ":doc"), vec!["error: usage: :doc symbol"]);
+        assert_eq!(command(&mut interp, ":env x"), vec!["error: usage: :env"]);
+        assert_eq!(command(&mut interp, ":id"), vec!["not a command"]);
+        command(&mut interp, ":reset");
+        assert_eq!(command(&mut interp, ":doc sq"), vec!["error: unbound symbol k='sq'"]);
+        assert_eq!(interp.get_global::<Vec<String>>("*argv*").unwrap(), vec!["a"]);
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join("felisp_test_load.flp");
+        std::fs::write(&path, "(defn loaded 1) (+ loaded 1)").unwrap();
+        let mut interp = Interpreter::new();
+        let lines = command(&mut interp, &format!(":load {}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines, vec!["🔥 => 2"]);
+    }
+
+    #[test]
+    fn test_word_start() {
+        assert_eq!(word_start("(ma", 3), 1);
+        assert_eq!(word_start("(map f '(fi", 11), 9);
+        assert_eq!(word_start("car", 3), 0);
+        assert_eq!(word_start("(f ", 3), 3);
+    }
+
+    #[test]
+    fn test_complete() {
+        let helper = FelispHelper::new(Rc::new(RefCell::new(default_env())));
+        let history = DefaultHistory::new();
+        let ctx = rustyline::Context::new(&history);
+        let (start, candidates) = helper.complete("(select myt", 11, &ctx).unwrap();
+        assert_eq!((start, candidates), (8, vec!["mytable1".to_string()]));
+        let (_, candidates) = helper.complete("(fl", 3, &ctx).unwrap();
+        assert_eq!(candidates, vec!["float", "floor"]);
+    }
+}
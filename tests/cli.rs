@@ -0,0 +1,50 @@
+// Runs the felisp binary the way a shell or cron job would
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn felisp(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_felisp"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+#[test]
+fn test_eval_expr() {
+    let out = felisp(&["-e", "(+ 1 2) (count *argv*)", "a", "b"], "");
+    assert_eq!(stdout(&out), "2\n");
+    assert_eq!(out.status.code(), Some(0));
+    let out = felisp(&["-e"], "");
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn test_script_file() {
+    let path = std::env::temp_dir().join("felisp_test_cli.flp");
+    fs::write(&path, "(defn n (count *argv*))\n(exit (+ n 10))\n").unwrap();
+    let out = felisp(&[path.to_str().unwrap(), "x", "y", "z"], "");
+    fs::remove_file(&path).unwrap();
+    assert_eq!(out.status.code(), Some(13));
+    let out = felisp(&["/no/such/script.flp"], "");
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn test_stdin_and_errors() {
+    let out = felisp(&["-", "arg"], "(insert mytable1 1 \"a\" \"a@x\")\n(first *argv*)");
+    assert_eq!(out.status.code(), Some(0));
+    let out = felisp(&["-"], "(defn f (fn () (first 1)))\n(f)");
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(stderr, "error: expected a list, got 1\n  in first\n  in f\n");
+}
@@ -0,0 +1,27 @@
+// Interrupting sets a process-wide flag, so it gets a test binary of its own
+use std::thread;
+use std::time::Duration;
+
+use felisp::lib::data::FelispErrKind;
+use felisp::lisp_core::eval::interrupt;
+use felisp::Interpreter;
+
+#[test]
+fn test_interrupt() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(defn spin (fn (n) (spin (+ n 1))))").unwrap();
+    let interrupter = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(50));
+        interrupt();
+    });
+    let err = interp.eval_str("(try (spin 0) (catch :error e :caught))").unwrap_err();
+    interrupter.join().unwrap();
+    assert!(matches!(err.kind, FelispErrKind::Interrupted));
+    assert_eq!(interp.eval_str("(+ 1 2)").unwrap().to_string(), "3");
+    // An interrupt while nothing is running does not stop the next form
+    interrupt();
+    assert_eq!(interp.eval_str("(+ 1 2)").unwrap().to_string(), "3");
+    interrupt();
+    let form = interp.read("(* 2 3)").unwrap().remove(0);
+    assert_eq!(interp.eval(&form).unwrap().to_string(), "6");
+}